@@ -1,9 +1,25 @@
+use serde::{Deserialize, Serialize};
 use serde_json::json;
 use worker::{Error as WorkerError, Response};
 use worker::kv::KvError;
 
 use crate::jwt::JwtError;
 
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FieldError {
+    pub field: String,
+    pub message: String,
+}
+
+impl FieldError {
+    pub fn new(field: &str, message: &str) -> Self {
+        Self {
+            field: field.to_owned(),
+            message: message.to_owned(),
+        }
+    }
+}
+
 #[derive(Debug, thiserror::Error)]
 pub enum ApiError {
     // users
@@ -23,16 +39,42 @@ pub enum ApiError {
     // challenges
     #[error("challenge not exists")]
     ChallengeNotExists,
+    #[error("stamp not exists")]
+    StampNotExists,
+    #[error("challenge version not exists")]
+    ChallengeVersionNotExists,
+    #[error("place mismatch: {0}")]
+    PlaceMismatch(String),
+
+    // claim codes
+    #[error("invalid claim code")]
+    InvalidClaimCode,
+    #[error("claim code already redeemed")]
+    ClaimCodeRedeemed,
 
     // foodnotes
     #[error("foodnote not exists")]
     FoodnoteNotExists,
+    #[error("not the author of the foodnote")]
+    NotFoodnoteAuthor,
+
+    // images
+    #[error("image not exists")]
+    ImageNotExists,
+    #[error("image too large")]
+    ImageTooLarge,
+    #[error("unsupported image")]
+    UnsupportedImage,
 
     // general
     #[error("bad request: {0}")]
     BadRequest(String),
+    #[error("invalid fields: {0:?}")]
+    InvalidFields(Vec<FieldError>),
     #[error("server error: {0}")]
     ServerError(String),
+    #[error("too many requests")]
+    TooManyRequests,
 
     // internals
     #[error("kv error")]
@@ -61,9 +103,20 @@ impl ApiError {
             ApiError::InvalidOAuthProvider => "invalid oauth provider",
             ApiError::InvalidOAuthToken => "invalid oauth token",
             ApiError::ChallengeNotExists => "challenge not exists",
+            ApiError::StampNotExists => "stamp not exists",
+            ApiError::ChallengeVersionNotExists => "challenge version not exists",
+            ApiError::PlaceMismatch(message) => message,
+            ApiError::InvalidClaimCode => "invalid claim code",
+            ApiError::ClaimCodeRedeemed => "claim code already redeemed",
             ApiError::FoodnoteNotExists => "foodnote not exists",
+            ApiError::NotFoodnoteAuthor => "not the author of the foodnote",
+            ApiError::ImageNotExists => "image not exists",
+            ApiError::ImageTooLarge => "image too large",
+            ApiError::UnsupportedImage => "unsupported image, only jpeg, png and webp",
             ApiError::BadRequest(message) => message,
+            ApiError::InvalidFields(_) => "invalid request fields",
             ApiError::ServerError(message) => message,
+            ApiError::TooManyRequests => "too many requests",
             _ => "internal server error",
         };
         let status_code: u16 = match self {
@@ -73,12 +126,26 @@ impl ApiError {
             ApiError::InvalidOAuthProvider => 400,
             ApiError::InvalidOAuthToken => 400,
             ApiError::ChallengeNotExists => 404,
+            ApiError::StampNotExists => 404,
+            ApiError::ChallengeVersionNotExists => 404,
+            ApiError::PlaceMismatch(_) => 422,
+            ApiError::InvalidClaimCode => 400,
+            ApiError::ClaimCodeRedeemed => 409,
             ApiError::FoodnoteNotExists => 404,
+            ApiError::NotFoodnoteAuthor => 403,
+            ApiError::ImageNotExists => 404,
+            ApiError::ImageTooLarge => 413,
+            ApiError::UnsupportedImage => 415,
             ApiError::BadRequest(_) => 400,
+            ApiError::InvalidFields(_) => 400,
+            ApiError::TooManyRequests => 429,
             _ => 500,
         };
 
-        let body = json!({ "message": message });
+        let body = match self {
+            ApiError::InvalidFields(fields) => json!({ "message": message, "fields": fields }),
+            _ => json!({ "message": message }),
+        };
 
         Response::from_json(&body).unwrap().with_status(status_code)
     }
@@ -6,7 +6,7 @@ use crate::api_error::ApiError;
 use crate::api_result::ApiResult;
 use crate::users::{User, UserClaims, Users};
 
-pub async fn authorize_access_token(users: &Users, req: Request) -> ApiResult<User> {
+pub async fn authorize_access_token(users: &Users, req: &Request) -> ApiResult<User> {
     let auth_header = req.headers().get("Authorization")?.unwrap_or("".to_owned());
     let token_str = get_auth_token_from_header(&auth_header)?;
 
@@ -33,7 +33,7 @@ pub async fn authorize_access_token(users: &Users, req: Request) -> ApiResult<Us
     }
 }
 
-pub async fn authorize_refresh_token(users: &Users, req: Request) -> ApiResult<User> {
+pub async fn authorize_refresh_token(users: &Users, req: &Request) -> ApiResult<User> {
     let auth_header = req.headers().get("Authorization")?.unwrap_or("".to_owned());
     let token_str = get_auth_token_from_header(&auth_header)?;
 
@@ -0,0 +1,118 @@
+use serde::{Deserialize, Serialize};
+
+use crate::challenges::Challenge;
+use crate::progress::ChallengeProgress;
+
+/// Awarded once an author has a foodnote for every stamp of a challenge.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Badge {
+    pub challenge_id: String,
+    pub completed_at: i64,
+    pub stamp_ids: Vec<String>,
+    pub foodnote_ids: Vec<String>,
+}
+
+impl Badge {
+    /// Creates a badge for a completed challenge; `None` while the challenge is still in progress.
+    pub fn for_progress(progress: &ChallengeProgress, completed_at: i64) -> Option<Self> {
+        if !progress.is_completed() {
+            return None;
+        }
+
+        let stamp_ids = progress
+            .collected
+            .iter()
+            .map(|x| x.stamp_id.to_owned())
+            .collect();
+        let foodnote_ids = progress
+            .collected
+            .iter()
+            .flat_map(|x| x.foodnote_ids.clone())
+            .collect();
+
+        Some(Self {
+            challenge_id: progress.challenge_id.to_owned(),
+            completed_at,
+            stamp_ids,
+            foodnote_ids,
+        })
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BadgeDto {
+    #[serde(flatten)]
+    pub badge: Badge,
+    /// Stamps were added to the challenge after the badge was earned. The badge stays valid.
+    pub new_stamps_available: bool,
+}
+
+impl BadgeDto {
+    pub fn new(badge: Badge, challenge: Option<&Challenge>) -> Self {
+        let new_stamps_available = match challenge {
+            Some(challenge) => challenge
+                .stamps
+                .iter()
+                .any(|x| !badge.stamp_ids.contains(&x.id)),
+            None => false,
+        };
+
+        Self {
+            badge,
+            new_stamps_available,
+        }
+    }
+}
+
+#[cfg(test)]
+mod badge_tests {
+    use super::*;
+    use crate::challenges::Stamp;
+    use crate::locales::Translations;
+    use crate::progress::collect_stamps;
+    use crate::test_fixtures::{challenge, foodnote};
+
+    #[test]
+    fn should_award_badge_for_completed_challenge() {
+        let challenge = challenge(&["a", "b"]);
+        let foodnotes = vec![foodnote("a", 10), foodnote("b", 20), foodnote("a", 30)];
+        let progress = ChallengeProgress::new(&challenge, &collect_stamps(&foodnotes));
+        let badge = Badge::for_progress(&progress, 40).unwrap();
+
+        assert_eq!(badge.challenge_id, challenge.id);
+        assert_eq!(badge.completed_at, 40);
+        assert_eq!(badge.stamp_ids, vec!["a", "b"]);
+        assert_eq!(badge.foodnote_ids.len(), 3);
+    }
+
+    #[test]
+    fn should_not_award_badge_for_challenge_in_progress() {
+        let challenge = challenge(&["a", "b"]);
+        let progress = ChallengeProgress::new(&challenge, &collect_stamps(&[foodnote("a", 10)]));
+
+        assert!(Badge::for_progress(&progress, 40).is_none());
+    }
+
+    #[test]
+    fn should_flag_new_stamps_added_after_completion() {
+        let mut challenge = challenge(&["a"]);
+        let progress = ChallengeProgress::new(&challenge, &collect_stamps(&[foodnote("a", 10)]));
+        let badge = Badge::for_progress(&progress, 40).unwrap();
+
+        assert!(!BadgeDto::new(badge.clone(), Some(&challenge)).new_stamps_available);
+
+        challenge.stamps.push(Stamp {
+            id: "b".to_string(),
+            title: "b".to_string(),
+            title_translations: Translations::new(),
+            description: "".to_string(),
+            description_translations: Translations::new(),
+            img_url: "".to_string(),
+            constraints: None,
+        });
+        let dto = BadgeDto::new(badge, Some(&challenge));
+
+        assert!(dto.new_stamps_available);
+        assert_eq!(dto.badge.stamp_ids, vec!["a"]);
+    }
+}
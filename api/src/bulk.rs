@@ -0,0 +1,621 @@
+use std::collections::HashMap;
+
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::api_error::{ApiError, FieldError};
+use crate::api_result::ApiResult;
+use crate::challenges::{Challenge, CreateChallengeDto, Stamp, SORT_ORDER_GAP};
+use crate::history::{diff_challenges, FieldChange};
+
+/// Columns describing the challenge, repeated on every row of its stamps.
+const CHALLENGE_COLUMNS: [&str; 10] = [
+    "challenge_id",
+    "name",
+    "name_translations",
+    "status",
+    "starts_at",
+    "ends_at",
+    "cover_img_url",
+    "tags",
+    "difficulty",
+    "points",
+];
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BulkFormat {
+    Json,
+    Csv,
+}
+
+impl BulkFormat {
+    /// Reads `?format=json|csv`, JSON by default.
+    pub fn parse(value: Option<&str>) -> ApiResult<Self> {
+        match value {
+            None | Some("json") => Ok(BulkFormat::Json),
+            Some("csv") => Ok(BulkFormat::Csv),
+            Some(x) => Err(ApiError::BadRequest(format!("unknown format \"{}\"", x))),
+        }
+    }
+}
+
+/// A challenge to import, updating the stored one with the same `id` or created otherwise.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ImportChallengeDto {
+    pub id: Option<String>,
+    #[serde(flatten)]
+    pub challenge: CreateChallengeDto,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ImportChallengesDto {
+    pub challenges: Vec<ImportChallengeDto>,
+}
+
+/// An imported challenge with the rows it was read from, counting from 1: the position in the JSON
+/// list, or the CSV line after the header.
+#[derive(Debug, Clone)]
+pub struct ImportItem {
+    pub rows: Vec<usize>,
+    pub dto: ImportChallengeDto,
+}
+
+impl ImportItem {
+    pub fn from_dtos(dtos: Vec<ImportChallengeDto>) -> Vec<Self> {
+        dtos.into_iter()
+            .enumerate()
+            .map(|(index, dto)| Self {
+                rows: vec![index + 1],
+                dto,
+            })
+            .collect()
+    }
+}
+
+/// A stamp per row, the challenge columns repeated on each. Nested values are JSON and tags are
+/// separated with `|`, so that the file stays editable in a spreadsheet.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ChallengeCsvRow {
+    pub challenge_id: String,
+    pub name: String,
+    pub name_translations: String,
+    pub status: String,
+    pub starts_at: Option<i64>,
+    pub ends_at: Option<i64>,
+    pub cover_img_url: String,
+    pub tags: String,
+    pub difficulty: String,
+    pub points: Option<u32>,
+    pub stamp_id: String,
+    pub stamp_title: String,
+    pub stamp_title_translations: String,
+    pub stamp_description: String,
+    pub stamp_description_translations: String,
+    pub stamp_img_url: String,
+    pub stamp_constraints: String,
+}
+
+impl ChallengeCsvRow {
+    fn new(challenge: &Challenge, stamp: Option<&Stamp>) -> Self {
+        let mut row = Self {
+            challenge_id: challenge.id.to_owned(),
+            name: challenge.name.to_owned(),
+            name_translations: to_json_cell(&challenge.name_translations),
+            status: to_json_cell(&challenge.status),
+            starts_at: challenge.starts_at,
+            ends_at: challenge.ends_at,
+            cover_img_url: challenge.cover_img_url.to_owned(),
+            tags: challenge.tags.join("|"),
+            difficulty: to_json_cell(&challenge.difficulty),
+            points: Some(challenge.points),
+            ..Self::default()
+        };
+
+        if let Some(stamp) = stamp {
+            row.stamp_id = stamp.id.to_owned();
+            row.stamp_title = stamp.title.to_owned();
+            row.stamp_title_translations = to_json_cell(&stamp.title_translations);
+            row.stamp_description = stamp.description.to_owned();
+            row.stamp_description_translations = to_json_cell(&stamp.description_translations);
+            row.stamp_img_url = stamp.img_url.to_owned();
+            row.stamp_constraints = to_json_cell(&stamp.constraints);
+        }
+
+        row
+    }
+
+    /// Rows of the same challenge share its id, or its name when it is new.
+    fn group_key(&self) -> String {
+        match self.challenge_id.trim().is_empty() {
+            true => format!("name:{}", self.name.trim()),
+            false => format!("id:{}", self.challenge_id.trim()),
+        }
+    }
+
+    fn has_stamp(&self) -> bool {
+        [
+            &self.stamp_id,
+            &self.stamp_title,
+            &self.stamp_title_translations,
+            &self.stamp_description,
+            &self.stamp_description_translations,
+            &self.stamp_img_url,
+            &self.stamp_constraints,
+        ]
+        .iter()
+        .any(|x| !x.trim().is_empty())
+    }
+
+    fn to_challenge(&self, field: &str, errors: &mut Vec<FieldError>) -> ImportChallengeDto {
+        let id = Some(self.challenge_id.trim().to_owned()).filter(|x| !x.is_empty());
+        let challenge = CreateChallengeDto {
+            name: self.name.to_owned(),
+            name_translations: from_json_cell(
+                field,
+                "name_translations",
+                &self.name_translations,
+                errors,
+            ),
+            stamps: vec![],
+            status: from_json_cell(field, "status", &self.status, errors),
+            starts_at: self.starts_at,
+            ends_at: self.ends_at,
+            cover_img_url: self.cover_img_url.trim().to_owned(),
+            tags: self
+                .tags
+                .split('|')
+                .map(|x| x.trim().to_owned())
+                .filter(|x| !x.is_empty())
+                .collect(),
+            difficulty: from_json_cell(field, "difficulty", &self.difficulty, errors),
+            points: self.points.unwrap_or_default(),
+        };
+
+        ImportChallengeDto { id, challenge }
+    }
+
+    fn to_stamp(&self, field: &str, errors: &mut Vec<FieldError>) -> Stamp {
+        Stamp {
+            id: self.stamp_id.trim().to_owned(),
+            title: self.stamp_title.to_owned(),
+            title_translations: from_json_cell(
+                field,
+                "stamp_title_translations",
+                &self.stamp_title_translations,
+                errors,
+            ),
+            description: self.stamp_description.to_owned(),
+            description_translations: from_json_cell(
+                field,
+                "stamp_description_translations",
+                &self.stamp_description_translations,
+                errors,
+            ),
+            img_url: self.stamp_img_url.trim().to_owned(),
+            constraints: from_json_cell(
+                field,
+                "stamp_constraints",
+                &self.stamp_constraints,
+                errors,
+            ),
+        }
+    }
+}
+
+/// Writes JSON for a cell, leaving it empty for `null` and empty maps.
+fn to_json_cell<T: Serialize>(value: &T) -> String {
+    match serde_json::to_value(value) {
+        Ok(Value::Null) => "".to_string(),
+        Ok(Value::Object(x)) if x.is_empty() => "".to_string(),
+        Ok(Value::String(x)) => x,
+        Ok(x) => x.to_string(),
+        Err(_) => "".to_string(),
+    }
+}
+
+/// Reads a cell written by `to_json_cell`, where plain words stand for JSON strings.
+fn from_json_cell<T: DeserializeOwned + Default>(
+    field: &str,
+    column: &str,
+    cell: &str,
+    errors: &mut Vec<FieldError>,
+) -> T {
+    let cell = cell.trim();
+    if cell.is_empty() {
+        return T::default();
+    }
+
+    let value =
+        serde_json::from_str::<Value>(cell).unwrap_or_else(|_| Value::String(cell.to_owned()));
+    match serde_json::from_value::<T>(value) {
+        Ok(x) => x,
+        Err(_) => {
+            errors.push(FieldError::new(
+                &format!("{}.{}", field, column),
+                "invalid value",
+            ));
+            T::default()
+        }
+    }
+}
+
+pub fn to_csv(challenges: &[Challenge]) -> ApiResult<String> {
+    let mut writer = csv::Writer::from_writer(vec![]);
+
+    for challenge in challenges {
+        let rows = match challenge.stamps.is_empty() {
+            true => vec![ChallengeCsvRow::new(challenge, None)],
+            false => challenge
+                .stamps
+                .iter()
+                .map(|x| ChallengeCsvRow::new(challenge, Some(x)))
+                .collect(),
+        };
+        for row in rows {
+            writer
+                .serialize(row)
+                .map_err(|e| ApiError::ServerError(e.to_string()))?;
+        }
+    }
+
+    let bytes = writer
+        .into_inner()
+        .map_err(|e| ApiError::ServerError(e.to_string()))?;
+
+    String::from_utf8(bytes).map_err(|e| ApiError::ServerError(e.to_string()))
+}
+
+/// Reads challenges from CSV rows grouped by challenge. Every malformed row is reported at once.
+pub fn from_csv(text: &str) -> ApiResult<Vec<ImportItem>> {
+    let mut reader = csv::Reader::from_reader(text.as_bytes());
+    let mut errors = Vec::<FieldError>::new();
+    let mut items = Vec::<(ChallengeCsvRow, ImportItem)>::new();
+    let mut indexes = HashMap::<String, usize>::new();
+
+    for (index, row) in reader.deserialize::<ChallengeCsvRow>().enumerate() {
+        let number = index + 1;
+        let field = format!("rows[{}]", number);
+        let row = match row {
+            Ok(x) => x,
+            Err(e) => {
+                errors.push(FieldError::new(&field, &e.to_string()));
+                continue;
+            }
+        };
+
+        let item_index = *indexes.entry(row.group_key()).or_insert_with(|| {
+            let dto = row.to_challenge(&field, &mut errors);
+            items.push((row.clone(), ImportItem { rows: vec![], dto }));
+            items.len() - 1
+        });
+        let (first, item) = &mut items[item_index];
+
+        for column in conflicting_columns(first, &row) {
+            errors.push(FieldError::new(
+                &format!("{}.{}", field, column),
+                &format!("conflicts with row {}", item.rows[0]),
+            ));
+        }
+        if row.has_stamp() {
+            item.dto
+                .challenge
+                .stamps
+                .push(row.to_stamp(&field, &mut errors));
+        }
+        item.rows.push(number);
+    }
+
+    match errors.is_empty() {
+        true => Ok(items.into_iter().map(|(_, item)| item).collect()),
+        false => Err(ApiError::InvalidFields(errors)),
+    }
+}
+
+/// Challenge columns the row fills in differently from the first row of the challenge.
+fn conflicting_columns(first: &ChallengeCsvRow, row: &ChallengeCsvRow) -> Vec<&'static str> {
+    let first = serde_json::to_value(first).unwrap_or_default();
+    let row = serde_json::to_value(row).unwrap_or_default();
+    let is_blank = |x: &Value| {
+        matches!(x, Value::Null) || matches!(x.as_str(), Some(x) if x.trim().is_empty())
+    };
+
+    CHALLENGE_COLUMNS
+        .iter()
+        .filter(|x| !is_blank(&row[**x]) && row[**x] != first[**x])
+        .copied()
+        .collect()
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ImportAction {
+    Create,
+    Update,
+    Unchanged,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ImportRowReport {
+    pub rows: Vec<usize>,
+    pub id: String,
+    pub name: String,
+    pub action: ImportAction,
+    pub changes: Vec<FieldChange>,
+    pub errors: Vec<FieldError>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ImportReport {
+    pub dry_run: bool,
+    /// Whether the import was written, which only happens when every challenge is valid.
+    pub applied: bool,
+    pub created: u32,
+    pub updated: u32,
+    pub unchanged: u32,
+    pub rows: Vec<ImportRowReport>,
+}
+
+impl ImportReport {
+    pub fn new(rows: Vec<ImportRowReport>, dry_run: bool) -> Self {
+        let count =
+            |action: ImportAction| rows.iter().filter(|x| x.action == action).count() as u32;
+
+        Self {
+            dry_run,
+            applied: !dry_run && rows.iter().all(|x| x.errors.is_empty()),
+            created: count(ImportAction::Create),
+            updated: count(ImportAction::Update),
+            unchanged: count(ImportAction::Unchanged),
+            rows,
+        }
+    }
+
+    pub fn is_valid(&self) -> bool {
+        self.rows.iter().all(|x| x.errors.is_empty())
+    }
+}
+
+/// Upserts `items` over the `existing` challenges without writing anything: returns the challenges
+/// to store, with a report for every item. New challenges are placed after `last_sort_order`.
+pub fn plan_import(
+    existing: &[Challenge],
+    items: &[ImportItem],
+    last_sort_order: i64,
+) -> (Vec<Challenge>, Vec<ImportRowReport>) {
+    let mut planned = Vec::<(Option<&Challenge>, Challenge, Vec<FieldError>)>::new();
+    let mut sort_order = last_sort_order;
+
+    for item in items {
+        let mut errors = Vec::<FieldError>::new();
+        let mut challenge = Challenge::new(&item.dto.challenge);
+        let before = item
+            .dto
+            .id
+            .as_ref()
+            .and_then(|id| existing.iter().find(|x| &x.id == id));
+
+        match (before, &item.dto.id) {
+            (Some(before), _) => {
+                challenge.id = before.id.to_owned();
+                challenge.archived_at = before.archived_at;
+                challenge.sort_order = before.sort_order;
+            }
+            (None, id) => {
+                if let Some(id) = id {
+                    if !is_challenge_id(id) {
+                        errors.push(FieldError::new(
+                            "id",
+                            "only alphanumerics and '-' are allowed",
+                        ));
+                    }
+                    challenge.id = id.to_owned();
+                }
+                sort_order += SORT_ORDER_GAP;
+                challenge.sort_order = sort_order;
+            }
+        }
+        if planned.iter().any(|(_, x, _)| x.id == challenge.id) {
+            errors.push(FieldError::new("id", "duplicated"));
+        }
+
+        planned.push((before, challenge, errors));
+    }
+
+    let untouched = existing
+        .iter()
+        .filter(|x| !planned.iter().any(|(_, planned, _)| planned.id == x.id))
+        .collect::<Vec<&Challenge>>();
+
+    let reports = planned
+        .iter()
+        .zip(items)
+        .enumerate()
+        .map(|(index, ((before, challenge, errors), item))| {
+            let taken_stamp_ids = untouched
+                .iter()
+                .copied()
+                .chain(
+                    planned
+                        .iter()
+                        .enumerate()
+                        .filter(|(i, _)| *i != index)
+                        .map(|(_, (_, x, _))| x),
+                )
+                .flat_map(|x| x.stamp_ids())
+                .collect::<Vec<String>>();
+
+            let mut errors = errors.clone();
+            if let Err(ApiError::InvalidFields(mut fields)) = challenge.validate(&taken_stamp_ids) {
+                errors.append(&mut fields);
+            }
+
+            let changes = diff_challenges(*before, challenge);
+            let action = match (before, changes.is_empty()) {
+                (None, _) => ImportAction::Create,
+                (Some(_), true) => ImportAction::Unchanged,
+                (Some(_), false) => ImportAction::Update,
+            };
+
+            ImportRowReport {
+                rows: item.rows.clone(),
+                id: challenge.id.to_owned(),
+                name: challenge.name.to_owned(),
+                action,
+                changes,
+                errors,
+            }
+        })
+        .collect();
+
+    let challenges = planned.into_iter().map(|(_, x, _)| x).collect();
+
+    (challenges, reports)
+}
+
+/// Storage keys end the challenge id with `_`, e.g. `history_<id>_<version>`, so an id with `_`
+/// could read the entries of another challenge.
+fn is_challenge_id(value: &str) -> bool {
+    !value.is_empty() && value.chars().all(|x| x.is_ascii_alphanumeric() || x == '-')
+}
+
+#[cfg(test)]
+mod bulk_tests {
+    use super::*;
+    use crate::challenges::ChallengeDifficulty;
+    use crate::constraints::PlaceConstraints;
+    use crate::test_fixtures::challenge;
+
+    fn hanwoo() -> Challenge {
+        let mut challenge = challenge(&["ribeye_roll", "sirloin"]);
+        challenge.id = "hanwoo".to_string();
+        challenge
+            .name_translations
+            .insert("en".to_string(), "Korean beef".to_string());
+        challenge.tags = vec!["beef".to_string(), "korean".to_string()];
+        challenge.difficulty = Some(ChallengeDifficulty::Hard);
+        challenge.points = 100;
+        challenge.stamps[1].description = "부드러운, \"채끝\"".to_string();
+        challenge.stamps[1].constraints = Some(PlaceConstraints {
+            regions: vec!["서울".to_string()],
+            ..PlaceConstraints::default()
+        });
+
+        challenge
+    }
+
+    fn items(challenges: &[Challenge]) -> Vec<ImportItem> {
+        let dtos = challenges
+            .iter()
+            .map(|x| {
+                let dto = serde_json::to_value(x).unwrap();
+                serde_json::from_value::<ImportChallengeDto>(dto).unwrap()
+            })
+            .collect();
+
+        ImportItem::from_dtos(dtos)
+    }
+
+    #[test]
+    fn should_import_exported_csv_unchanged() {
+        let mut empty = challenge(&[]);
+        empty.id = "empty".to_string();
+        let existing = vec![hanwoo(), empty];
+
+        let csv = to_csv(&existing).unwrap();
+        let items = from_csv(&csv).unwrap();
+
+        assert_eq!(csv.lines().count(), 4);
+        assert_eq!(items.len(), 2);
+        assert_eq!(items[0].rows, vec![1, 2]);
+        assert!(items[1].dto.challenge.stamps.is_empty());
+
+        let (_, reports) = plan_import(&existing, &items, 0);
+        assert!(reports.iter().all(|x| x.action == ImportAction::Unchanged));
+        assert!(reports.iter().all(|x| x.errors.is_empty()));
+    }
+
+    #[test]
+    fn should_err_with_conflicting_and_invalid_rows() {
+        let csv = "challenge_id,name,name_translations,status,starts_at,ends_at,cover_img_url,tags,difficulty,points,stamp_id,stamp_title,stamp_title_translations,stamp_description,stamp_description_translations,stamp_img_url,stamp_constraints
+,냉면,,published,,,,,,,a,물냉,,,,,
+,냉면,,draft,,,,,,,b,비냉,,,,,
+,곱창,,,,,,,extreme,,c,곱창,,,,,
+";
+
+        match from_csv(csv).unwrap_err() {
+            ApiError::InvalidFields(fields) => assert_eq!(
+                fields,
+                vec![
+                    FieldError::new("rows[2].status", "conflicts with row 1"),
+                    FieldError::new("rows[3].difficulty", "invalid value"),
+                ]
+            ),
+            e => panic!("unexpected error: {:?}", e),
+        }
+    }
+
+    #[test]
+    fn should_plan_creates_and_updates_with_diff() {
+        let existing = vec![hanwoo()];
+        let mut updated = hanwoo();
+        updated.points = 200;
+        updated.stamps[0].title = "꽃등심".to_string();
+        let created = challenge(&["naengmyeon"]);
+
+        let (planned, reports) = plan_import(&existing, &items(&[updated, created]), 1024);
+
+        assert_eq!(reports[0].action, ImportAction::Update);
+        assert_eq!(
+            reports[0]
+                .changes
+                .iter()
+                .map(|x| x.field.as_str())
+                .collect::<Vec<&str>>(),
+            vec!["points", "stamps.ribeye_roll.title"]
+        );
+        assert_eq!(reports[1].action, ImportAction::Create);
+        assert_eq!(planned[1].sort_order, 2048);
+        assert!(ImportReport::new(reports, false).applied);
+    }
+
+    #[test]
+    fn should_not_apply_import_with_errors() {
+        let existing = vec![hanwoo()];
+        let mut taken = challenge(&["sirloin"]);
+        taken.name = "".to_string();
+
+        let (_, reports) = plan_import(&existing, &items(&[taken]), 0);
+
+        assert_eq!(
+            reports[0].errors,
+            vec![
+                FieldError::new("name", "required"),
+                FieldError::new("stamps[0].id", "duplicated"),
+            ]
+        );
+
+        let report = ImportReport::new(reports, false);
+        assert!(!report.is_valid());
+        assert!(!report.applied);
+    }
+
+    #[test]
+    fn should_err_with_underscore_in_new_challenge_id() {
+        let mut created = challenge(&["naengmyeon"]);
+        created.id = "hanwoo_2".to_string();
+        let mut dashed = challenge(&["bibim"]);
+        dashed.id = "hanwoo-2".to_string();
+
+        let (_, reports) = plan_import(&[], &items(&[created, dashed]), 0);
+
+        assert_eq!(
+            reports[0].errors,
+            vec![FieldError::new(
+                "id",
+                "only alphanumerics and '-' are allowed"
+            )]
+        );
+        assert!(reports[1].errors.is_empty());
+    }
+}
@@ -1,38 +1,176 @@
-use serde::{Deserialize, Serialize};
+use std::cmp::Ordering;
+use std::collections::HashMap;
+
+use chrono::Utc;
+use serde::{Deserialize, Deserializer, Serialize};
 use serde_json::json;
 use worker::*;
 
-use crate::api_error::ApiError;
+use crate::api_error::{ApiError, FieldError};
 use crate::api_result::ApiResult;
-use crate::durable::DurableStorageFind;
-use crate::req::ParseReqJson;
+use crate::bulk::{
+    from_csv, plan_import, to_csv, BulkFormat, ImportAction, ImportChallengesDto, ImportItem,
+    ImportReport,
+};
+use crate::claim_codes::{
+    get_jwt_for_claim_codes, sign_claim_code, ClaimCode, ClaimCodeDto, CreateClaimCodeDto,
+};
+use crate::constraints::PlaceConstraints;
+use crate::durable::{fetch_internal, get_durable_stub, DurableStorageFind};
+use crate::foodnotes::{RefreshChallengesDto, StampReferencesDto};
+use crate::history::{diff_challenges, ChallengeVersion, FieldChange, RollbackDto};
+use crate::identity::{find_identity, get_identity, is_admin_route};
+use crate::locales::{localize, preferred_languages, validate_translations, Translations};
+use crate::req::{path_segments, ParseReqJson, ReqQuery};
 use crate::res::{response, response_with_cache};
 use crate::uid;
 
 const ID_PREFIX: &str = "id_";
+const PARTICIPANT_COUNT_PREFIX: &str = "participant_count_";
+const JOINED_PREFIX: &str = "joined_";
+const HISTORY_PREFIX: &str = "history_";
+const LATEST_VERSION_PREFIX: &str = "latest_version_";
+const SCHEDULE_CHECKED_AT_KEY: &str = "schedule_checked_at";
+/// Space left between the sort orders of neighbouring challenges, so that moving one between two
+/// others only rewrites the moved one.
+pub const SORT_ORDER_GAP: i64 = 1024;
 
 pub fn challenge_id_key(id: &str) -> String {
     format!("{}{}", ID_PREFIX, id)
 }
 
+pub fn participant_count_key(challenge_id: &str) -> String {
+    format!("{}{}", PARTICIPANT_COUNT_PREFIX, challenge_id)
+}
+
+pub fn joined_prefix(user_id: &str) -> String {
+    format!("{}{}_", JOINED_PREFIX, user_id)
+}
+
+pub fn joined_key(user_id: &str, challenge_id: &str) -> String {
+    format!("{}{}", joined_prefix(user_id), challenge_id)
+}
+
+pub fn history_prefix(challenge_id: &str) -> String {
+    format!("{}{}_", HISTORY_PREFIX, challenge_id)
+}
+
+/// Versions are zero-padded so that listing the history returns them in order.
+pub fn history_key(challenge_id: &str, version: u32) -> String {
+    format!("{}{:010}", history_prefix(challenge_id), version)
+}
+
+pub fn latest_version_key(challenge_id: &str) -> String {
+    format!("{}{}", LATEST_VERSION_PREFIX, challenge_id)
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ChallengeStatus {
+    Draft,
+    #[default]
+    Published,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ChallengeDifficulty {
+    Easy,
+    Normal,
+    Hard,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct CreateChallengeDto {
     pub name: String,
+    #[serde(default)]
+    pub name_translations: Translations,
     pub stamps: Vec<Stamp>,
+    #[serde(default)]
+    pub status: ChallengeStatus,
+    pub starts_at: Option<i64>,
+    pub ends_at: Option<i64>,
+    #[serde(default)]
+    pub cover_img_url: String,
+    #[serde(default)]
+    pub tags: Vec<String>,
+    pub difficulty: Option<ChallengeDifficulty>,
+    #[serde(default)]
+    pub points: u32,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct UpdateChallengeDto {
     pub id: String,
     pub name: Option<String>,
+    pub name_translations: Option<Translations>,
     pub stamps: Option<Vec<Stamp>>,
+    pub status: Option<ChallengeStatus>,
+    // note: `null` clears the date, while a missing field leaves it as it is.
+    #[serde(default, deserialize_with = "deserialize_some")]
+    pub starts_at: Option<Option<i64>>,
+    #[serde(default, deserialize_with = "deserialize_some")]
+    pub ends_at: Option<Option<i64>>,
+    pub cover_img_url: Option<String>,
+    pub tags: Option<Vec<String>>,
+    #[serde(default, deserialize_with = "deserialize_some")]
+    pub difficulty: Option<Option<ChallengeDifficulty>>,
+    pub points: Option<u32>,
+}
+
+fn deserialize_some<'de, T, D>(deserializer: D) -> std::result::Result<Option<T>, D::Error>
+where
+    T: Deserialize<'de>,
+    D: Deserializer<'de>,
+{
+    Deserialize::deserialize(deserializer).map(Some)
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UpdateStampDto {
+    pub title: Option<String>,
+    pub title_translations: Option<Translations>,
+    pub description: Option<String>,
+    pub description_translations: Option<Translations>,
+    pub img_url: Option<String>,
+    #[serde(default, deserialize_with = "deserialize_some")]
+    pub constraints: Option<Option<PlaceConstraints>>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ReorderStampsDto {
+    pub stamp_ids: Vec<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MoveChallengeDto {
+    /// The challenge to place it right after, or `None` to place it first.
+    pub after_id: Option<String>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Challenge {
     pub id: String,
+    /// The name in the default locale, which clients without translations keep reading.
     pub name: String,
+    #[serde(default, skip_serializing_if = "Translations::is_empty")]
+    pub name_translations: Translations,
     pub stamps: Vec<Stamp>,
+    #[serde(default)]
+    pub status: ChallengeStatus,
+    pub starts_at: Option<i64>,
+    pub ends_at: Option<i64>,
+    pub archived_at: Option<i64>,
+    #[serde(default)]
+    pub cover_img_url: String,
+    #[serde(default)]
+    pub tags: Vec<String>,
+    pub difficulty: Option<ChallengeDifficulty>,
+    #[serde(default)]
+    pub points: u32,
+    /// Position in listings, ascending. Challenges stored before it existed share `0`.
+    #[serde(default)]
+    pub sort_order: i64,
 }
 
 impl Challenge {
@@ -42,10 +180,63 @@ impl Challenge {
         Self {
             id,
             name: dto.name.to_owned(),
+            name_translations: dto.name_translations.clone(),
             stamps: dto.stamps.clone(),
+            status: dto.status,
+            starts_at: dto.starts_at,
+            ends_at: dto.ends_at,
+            archived_at: None,
+            cover_img_url: dto.cover_img_url.to_owned(),
+            tags: dto.tags.clone(),
+            difficulty: dto.difficulty,
+            points: dto.points,
+            sort_order: 0,
         }
     }
 
+    /// Whether the challenge is published and `now` falls in its schedule.
+    pub fn is_active_at(&self, now: i64) -> bool {
+        self.status == ChallengeStatus::Published
+            && !self.is_archived()
+            && !matches!(self.starts_at, Some(x) if now < x)
+            && !matches!(self.ends_at, Some(x) if x <= now)
+    }
+
+    /// The first schedule boundary after `now`, where the challenge switches on or off.
+    pub fn next_boundary_after(&self, now: i64) -> Option<i64> {
+        [self.starts_at, self.ends_at]
+            .iter()
+            .flatten()
+            .filter(|x| **x > now)
+            .min()
+            .copied()
+    }
+
+    /// Whether the challenge switched on or off after `from`, up to `to`.
+    pub fn switches_between(&self, from: i64, to: i64) -> bool {
+        matches!(self.next_boundary_after(from), Some(x) if x <= to)
+    }
+
+    pub fn is_archived(&self) -> bool {
+        self.archived_at.is_some()
+    }
+
+    pub fn archive(&mut self) -> &mut Self {
+        self.archived_at = Some(Utc::now().timestamp());
+
+        self
+    }
+
+    pub fn restore(&mut self) -> &mut Self {
+        self.archived_at = None;
+
+        self
+    }
+
+    pub fn stamp_ids(&self) -> Vec<String> {
+        self.stamps.iter().map(|x| x.id.to_owned()).collect()
+    }
+
     pub fn id_key(&self) -> String {
         challenge_id_key(&self.id)
     }
@@ -54,20 +245,527 @@ impl Challenge {
         if let Some(name) = &updates.name {
             self.name = name.to_owned();
         }
+        if let Some(name_translations) = &updates.name_translations {
+            self.name_translations = name_translations.clone();
+        }
         if let Some(stamps) = &updates.stamps {
             self.stamps = stamps.clone();
         }
+        if let Some(status) = updates.status {
+            self.status = status;
+        }
+        if let Some(starts_at) = updates.starts_at {
+            self.starts_at = starts_at;
+        }
+        if let Some(ends_at) = updates.ends_at {
+            self.ends_at = ends_at;
+        }
+        if let Some(cover_img_url) = &updates.cover_img_url {
+            self.cover_img_url = cover_img_url.to_owned();
+        }
+        if let Some(tags) = &updates.tags {
+            self.tags = tags.clone();
+        }
+        if let Some(difficulty) = updates.difficulty {
+            self.difficulty = difficulty;
+        }
+        if let Some(points) = updates.points {
+            self.points = points;
+        }
+
+        self
+    }
+
+    pub fn get_stamp(&self, stamp_id: &str) -> ApiResult<&Stamp> {
+        match self.stamps.iter().find(|x| x.id == stamp_id) {
+            Some(x) => Ok(x),
+            None => Err(ApiError::StampNotExists),
+        }
+    }
+
+    pub fn update_stamp(&mut self, stamp_id: &str, updates: &UpdateStampDto) -> ApiResult<&Stamp> {
+        match self.stamps.iter_mut().find(|x| x.id == stamp_id) {
+            Some(x) => Ok(x.update(updates)),
+            None => Err(ApiError::StampNotExists),
+        }
+    }
+
+    /// Replaces the texts with their variants for `languages`, leaving out the translations.
+    pub fn localize(&mut self, languages: &[String]) -> &mut Self {
+        self.name = localize(&self.name, &self.name_translations, languages);
+        self.name_translations.clear();
+        for stamp in self.stamps.iter_mut() {
+            stamp.localize(languages);
+        }
 
         self
     }
+
+    pub fn remove_stamp(&mut self, stamp_id: &str) -> ApiResult<Stamp> {
+        match self.stamps.iter().position(|x| x.id == stamp_id) {
+            Some(index) => Ok(self.stamps.remove(index)),
+            None => Err(ApiError::StampNotExists),
+        }
+    }
+
+    /// Reorders stamps to follow `stamp_ids`, which must list every stamp of the challenge once.
+    pub fn reorder_stamps(&mut self, stamp_ids: &[String]) -> ApiResult<()> {
+        let mut stamps = Vec::<Stamp>::new();
+
+        for stamp_id in stamp_ids {
+            stamps.push(self.remove_stamp(stamp_id).map_err(|_| {
+                ApiError::BadRequest(format!("stamp \"{}\" is unknown or repeated", stamp_id))
+            })?);
+        }
+        if !self.stamps.is_empty() {
+            return Err(ApiError::BadRequest(
+                "stamp_ids must include every stamp".to_string(),
+            ));
+        }
+
+        self.stamps = stamps;
+
+        Ok(())
+    }
+
+    /// Validates required fields and that stamp ids are unique, both inside the challenge and
+    /// against `taken_stamp_ids` from other challenges, since foodnotes refer to stamps by id only.
+    pub fn validate(&self, taken_stamp_ids: &[String]) -> ApiResult<()> {
+        let mut errors = Vec::<FieldError>::new();
+
+        if self.name.trim().is_empty() {
+            errors.push(FieldError::new("name", "required"));
+        }
+        errors.append(&mut validate_translations(
+            "name_translations",
+            &self.name_translations,
+        ));
+        if let (Some(starts_at), Some(ends_at)) = (self.starts_at, self.ends_at) {
+            if ends_at <= starts_at {
+                errors.push(FieldError::new("ends_at", "must be after starts_at"));
+            }
+        }
+        if !self.cover_img_url.is_empty() && !is_http_url(&self.cover_img_url) {
+            errors.push(FieldError::new("cover_img_url", "invalid url"));
+        }
+        for (index, tag) in self.tags.iter().enumerate() {
+            let field = format!("tags[{}]", index);
+            if !is_tag(tag) {
+                errors.push(FieldError::new(
+                    &field,
+                    "only lowercase alphanumerics, '_' and '-' are allowed",
+                ));
+            } else if self.tags[..index].contains(tag) {
+                errors.push(FieldError::new(&field, "duplicated"));
+            }
+        }
+
+        for (index, stamp) in self.stamps.iter().enumerate() {
+            let field = format!("stamps[{}]", index);
+            errors.append(&mut stamp.validate(&field));
+
+            let duplicated = self.stamps[..index].iter().any(|x| x.id == stamp.id)
+                || taken_stamp_ids.contains(&stamp.id);
+            if duplicated {
+                errors.push(FieldError::new(&format!("{}.id", field), "duplicated"));
+            }
+        }
+
+        match errors.is_empty() {
+            true => Ok(()),
+            false => Err(ApiError::InvalidFields(errors)),
+        }
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Stamp {
     pub id: String,
     pub title: String,
+    #[serde(default, skip_serializing_if = "Translations::is_empty")]
+    pub title_translations: Translations,
     pub description: String,
+    #[serde(default, skip_serializing_if = "Translations::is_empty")]
+    pub description_translations: Translations,
     pub img_url: String,
+    /// Places the stamp can be collected at; anywhere when not set.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub constraints: Option<PlaceConstraints>,
+}
+
+impl Stamp {
+    pub fn update(&mut self, updates: &UpdateStampDto) -> &Self {
+        if let Some(title) = &updates.title {
+            self.title = title.to_owned();
+        }
+        if let Some(title_translations) = &updates.title_translations {
+            self.title_translations = title_translations.clone();
+        }
+        if let Some(description) = &updates.description {
+            self.description = description.to_owned();
+        }
+        if let Some(description_translations) = &updates.description_translations {
+            self.description_translations = description_translations.clone();
+        }
+        if let Some(img_url) = &updates.img_url {
+            self.img_url = img_url.to_owned();
+        }
+        if let Some(constraints) = &updates.constraints {
+            self.constraints = constraints.clone();
+        }
+
+        self
+    }
+
+    pub fn localize(&mut self, languages: &[String]) -> &mut Self {
+        self.title = localize(&self.title, &self.title_translations, languages);
+        self.title_translations.clear();
+        self.description = localize(&self.description, &self.description_translations, languages);
+        self.description_translations.clear();
+
+        self
+    }
+
+    pub fn validate(&self, field: &str) -> Vec<FieldError> {
+        let mut errors = Vec::<FieldError>::new();
+
+        if self.id.is_empty() {
+            errors.push(FieldError::new(&format!("{}.id", field), "required"));
+        } else if !self
+            .id
+            .chars()
+            .all(|x| x.is_ascii_alphanumeric() || x == '_' || x == '-')
+        {
+            errors.push(FieldError::new(
+                &format!("{}.id", field),
+                "only alphanumerics, '_' and '-' are allowed",
+            ));
+        }
+        if self.title.trim().is_empty() {
+            errors.push(FieldError::new(&format!("{}.title", field), "required"));
+        }
+        errors.append(&mut validate_translations(
+            &format!("{}.title_translations", field),
+            &self.title_translations,
+        ));
+        errors.append(&mut validate_translations(
+            &format!("{}.description_translations", field),
+            &self.description_translations,
+        ));
+        if !self.img_url.is_empty() && !is_http_url(&self.img_url) {
+            errors.push(FieldError::new(
+                &format!("{}.img_url", field),
+                "invalid url",
+            ));
+        }
+        if let Some(constraints) = &self.constraints {
+            errors.append(&mut constraints.validate(&format!("{}.constraints", field)));
+        }
+
+        errors
+    }
+}
+
+fn is_tag(value: &str) -> bool {
+    !value.is_empty()
+        && value.len() <= 32
+        && value
+            .chars()
+            .all(|x| x.is_ascii_lowercase() || x.is_ascii_digit() || x == '_' || x == '-')
+}
+
+fn is_http_url(value: &str) -> bool {
+    match Url::parse(value) {
+        Ok(url) => url.scheme() == "http" || url.scheme() == "https",
+        Err(_) => false,
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ChallengeDto {
+    #[serde(flatten)]
+    pub challenge: Challenge,
+    pub participant_count: u32,
+    /// Only present when the request is authenticated.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub joined: Option<bool>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct ChallengeFilter {
+    pub include_archived: bool,
+    pub include_inactive: bool,
+    /// Challenges with any of these tags; every challenge when empty.
+    pub tags: Vec<String>,
+}
+
+impl ChallengeFilter {
+    pub fn all() -> Self {
+        Self {
+            include_archived: true,
+            include_inactive: true,
+            tags: vec![],
+        }
+    }
+
+    /// Reads `?include_archived=true`, `?include_inactive=true` and `?tag=a,b`.
+    pub fn from_req(req: &Request) -> ApiResult<Self> {
+        let include_archived = req.query_param("include_archived")?;
+        let include_inactive = req.query_param("include_inactive")?;
+        let tags = req
+            .query_param("tag")?
+            .map(|x| {
+                x.split(',')
+                    .map(|x| x.trim().to_owned())
+                    .filter(|x| !x.is_empty())
+                    .collect()
+            })
+            .unwrap_or_default();
+
+        Ok(Self {
+            include_archived: include_archived.as_deref() == Some("true"),
+            include_inactive: include_inactive.as_deref() == Some("true"),
+            tags,
+        })
+    }
+
+    /// Whether the filter asks for the admin view, which the gateway only lets admins through.
+    pub fn is_admin_view(&self) -> bool {
+        self.include_archived || self.include_inactive
+    }
+
+    pub fn matches(&self, challenge: &Challenge, now: i64) -> bool {
+        if !self.tags.is_empty() && !challenge.tags.iter().any(|x| self.tags.contains(x)) {
+            return false;
+        }
+        if challenge.is_archived() {
+            return self.include_archived;
+        }
+
+        self.include_inactive || challenge.is_active_at(now)
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum ChallengeSortField {
+    /// The order set by admins.
+    #[default]
+    Order,
+    Name,
+    Points,
+    Difficulty,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct ChallengeSort {
+    pub field: ChallengeSortField,
+    pub descending: bool,
+}
+
+impl ChallengeSort {
+    /// Reads `?sort=`, e.g. `points` or `-points` for the descending order.
+    pub fn from_req(req: &Request) -> ApiResult<Self> {
+        match req.query_param("sort")? {
+            Some(sort) => Self::parse(&sort),
+            None => Ok(Self::default()),
+        }
+    }
+
+    pub fn parse(value: &str) -> ApiResult<Self> {
+        let (descending, name) = match value.strip_prefix('-') {
+            Some(name) => (true, name),
+            None => (false, value),
+        };
+        let field = match name {
+            "order" => ChallengeSortField::Order,
+            "name" => ChallengeSortField::Name,
+            "points" => ChallengeSortField::Points,
+            "difficulty" => ChallengeSortField::Difficulty,
+            _ => return Err(ApiError::BadRequest(format!("unknown sort \"{}\"", value))),
+        };
+
+        Ok(Self { field, descending })
+    }
+
+    /// Compares by the field, then by the order set by admins.
+    pub fn compare(&self, a: &Challenge, b: &Challenge) -> Ordering {
+        let ordering = match self.field {
+            ChallengeSortField::Order => Ordering::Equal,
+            ChallengeSortField::Name => a.name.cmp(&b.name),
+            ChallengeSortField::Points => a.points.cmp(&b.points),
+            ChallengeSortField::Difficulty => a.difficulty.cmp(&b.difficulty),
+        };
+        let ordering = match self.descending {
+            true => ordering.reverse(),
+            false => ordering,
+        };
+
+        ordering.then_with(|| {
+            let by_order = a
+                .sort_order
+                .cmp(&b.sort_order)
+                .then_with(|| a.id.cmp(&b.id));
+            match self.descending && self.field == ChallengeSortField::Order {
+                true => by_order.reverse(),
+                false => by_order,
+            }
+        })
+    }
+}
+
+/// A sort order between the challenges ordered at `previous` and `next`, or `None` when there is
+/// no room left between them and every challenge must be renumbered.
+pub fn sort_order_between(previous: Option<i64>, next: Option<i64>) -> Option<i64> {
+    match (previous, next) {
+        (None, None) => Some(0),
+        (Some(previous), None) => Some(previous + SORT_ORDER_GAP),
+        (None, Some(next)) => Some(next - SORT_ORDER_GAP),
+        (Some(previous), Some(next)) if next - previous > 1 => {
+            Some(previous + (next - previous) / 2)
+        }
+        _ => None,
+    }
+}
+
+/// Seconds a response over `challenges` may be cached: at most `max_age`, and never past the next
+/// schedule boundary so that challenges switch on and off on time.
+///
+/// note: `worker` 0.0.9 does not expose Durable Object alarms, so schedules are evaluated on read
+/// and this is what invalidates caches at the boundaries. What foodnotes derive from schedules is
+/// caught up by the cron trigger, see `Challenges::apply_schedule_transitions`.
+pub fn cache_max_age<'a>(
+    challenges: impl IntoIterator<Item = &'a Challenge>,
+    now: i64,
+    max_age: i32,
+) -> i32 {
+    challenges
+        .into_iter()
+        .filter_map(|x| x.next_boundary_after(now))
+        .map(|x| (x - now).min(max_age as i64) as i32)
+        .fold(max_age, i32::min)
+}
+
+/// Reads challenges from other durable objects.
+pub struct ChallengesClient {
+    stub: Stub,
+}
+
+impl ChallengesClient {
+    pub fn new(env: &Env) -> ApiResult<Self> {
+        let stub = get_durable_stub(env, "CHALLENGES")?;
+
+        Ok(Self { stub })
+    }
+
+    /// Lists every challenge, including archived and inactive ones.
+    pub async fn list_all(&self) -> ApiResult<Vec<Challenge>> {
+        #[derive(Deserialize)]
+        struct ChallengeList {
+            challenges: Vec<Challenge>,
+        }
+
+        let res = fetch_internal::<(), ChallengeList>(
+            &self.stub,
+            Method::Get,
+            "/internal/challenges",
+            None,
+        )
+        .await?;
+
+        Ok(res.challenges)
+    }
+
+    /// Lists the challenges active now, the only ones foodnotes can be written for.
+    pub async fn list_active(&self) -> ApiResult<Vec<Challenge>> {
+        let now = Utc::now().timestamp();
+        let mut challenges = self.list_all().await?;
+        challenges.retain(|x| x.is_active_at(now));
+
+        Ok(challenges)
+    }
+
+    /// Applies the schedule boundaries passed since the last call. Returns how many challenges
+    /// switched on or off.
+    pub async fn apply_schedule_transitions(&self) -> ApiResult<u32> {
+        #[derive(Deserialize)]
+        struct Transitions {
+            switched: u32,
+        }
+
+        let res = fetch_internal::<(), Transitions>(
+            &self.stub,
+            Method::Post,
+            "/internal/challenges/transitions",
+            None,
+        )
+        .await?;
+
+        Ok(res.switched)
+    }
+
+    pub async fn get_by_id(&self, id: &str) -> ApiResult<Challenge> {
+        let challenge = self.list_active().await?.into_iter().find(|x| x.id == id);
+
+        match challenge {
+            Some(x) => Ok(x),
+            None => Err(ApiError::ChallengeNotExists),
+        }
+    }
+}
+
+/// The stamps of `before` that `after` no longer has.
+pub fn removed_stamp_ids(before: &Challenge, after: &Challenge) -> Vec<String> {
+    before
+        .stamp_ids()
+        .into_iter()
+        .filter(|id| after.get_stamp(id).is_err())
+        .collect()
+}
+
+/// A stamp foodnotes claim can't be removed: another challenge could reuse its id and take over
+/// the foodnotes, collected stamps and badges.
+pub fn check_stamp_removal(
+    before: &Challenge,
+    after: &Challenge,
+    referenced: &[String],
+) -> ApiResult<()> {
+    let claimed = removed_stamp_ids(before, after)
+        .into_iter()
+        .filter(|x| referenced.contains(x))
+        .collect::<Vec<String>>();
+
+    match claimed.is_empty() {
+        true => Ok(()),
+        false => Err(ApiError::InvalidFields(vec![FieldError::new(
+            "stamps",
+            &format!(
+                "claimed by foodnotes, can't be removed: {}",
+                claimed.join(", ")
+            ),
+        )])),
+    }
+}
+
+/// Finds a stamp among the challenges active at `now`.
+pub fn find_active_stamp(challenges: &[Challenge], stamp_id: &str, now: i64) -> ApiResult<Stamp> {
+    let stamp = challenges
+        .iter()
+        .filter(|x| x.is_active_at(now))
+        .find_map(|x| x.get_stamp(stamp_id).ok());
+
+    match stamp {
+        Some(x) => Ok(x.to_owned()),
+        None => Err(ApiError::StampNotExists),
+    }
+}
+
+/// The challenges containing any of `stamp_ids`.
+pub fn challenges_with_stamps(challenges: &[Challenge], stamp_ids: &[&str]) -> Vec<Challenge> {
+    challenges
+        .iter()
+        .filter(|x| stamp_ids.iter().any(|id| x.get_stamp(id).is_ok()))
+        .cloned()
+        .collect()
 }
 
 #[durable_object]
@@ -93,83 +791,1456 @@ impl Challenges {
         }
     }
 
-    pub async fn list(&self) -> ApiResult<Vec<Challenge>> {
+    /// Inactive challenges are only found for admins, so drafts and schedules don't leak.
+    pub async fn get_visible_by_id(
+        &self,
+        id: &str,
+        include_inactive: bool,
+    ) -> ApiResult<Challenge> {
+        let challenge = self.get_by_id(id).await?;
+
+        match include_inactive || challenge.is_active_at(Utc::now().timestamp()) {
+            true => Ok(challenge),
+            false => Err(ApiError::ChallengeNotExists),
+        }
+    }
+
+    /// Lists challenges matching `filter`. By default only active published ones are listed.
+    pub async fn list(&self, filter: &ChallengeFilter) -> ApiResult<Vec<Challenge>> {
         let storage = self.state.storage();
         let mut challenges = Vec::<Challenge>::new();
+        let now = Utc::now().timestamp();
 
         let options = ListOptions::new().prefix(ID_PREFIX);
         let entries = storage.list_with_options(options).await?;
 
         entries.for_each(&mut |value, _| {
-            challenges.push(value.into_serde::<Challenge>().unwrap());
+            let challenge = value.into_serde::<Challenge>().unwrap();
+            if filter.matches(&challenge, now) {
+                challenges.push(challenge);
+            }
         });
 
         Ok(challenges.to_owned())
     }
 
-    pub async fn update(&self, challenge: &Challenge) -> ApiResult<()> {
+    /// Stores the challenge, keeping a version of it in its history when anything changed.
+    pub async fn update(&self, challenge: &Challenge, editor_id: Option<&str>) -> ApiResult<()> {
+        self.store(challenge, editor_id, None).await
+    }
+
+    async fn store(
+        &self,
+        challenge: &Challenge,
+        editor_id: Option<&str>,
+        rolled_back_to: Option<u32>,
+    ) -> ApiResult<()> {
+        let before = self.find_by_id(&challenge.id).await?;
+        let changes = diff_challenges(before.as_ref(), challenge);
+
         self.state
             .storage()
             .put(&challenge.id_key(), &challenge)
             .await?;
 
+        if !changes.is_empty() || rolled_back_to.is_some() {
+            let snapshot = Some(challenge.clone());
+            self.add_version(&challenge.id, snapshot, changes, editor_id, rolled_back_to)
+                .await?;
+        }
+
         Ok(())
     }
-}
 
-pub async fn create_challenge(challenges: &Challenges, mut req: Request) -> ApiResult<Challenge> {
-    let dto = req.parse_json::<CreateChallengeDto>().await?;
-    let challenge = Challenge::new(&dto);
+    async fn add_version(
+        &self,
+        challenge_id: &str,
+        challenge: Option<Challenge>,
+        changes: Vec<FieldChange>,
+        editor_id: Option<&str>,
+        rolled_back_to: Option<u32>,
+    ) -> ApiResult<()> {
+        let mut storage = self.state.storage();
+        let latest_key = latest_version_key(challenge_id);
+        let version = storage.find::<u32>(&latest_key).await?.unwrap_or(0) + 1;
 
-    challenges.update(&challenge).await?;
+        let record = ChallengeVersion {
+            version,
+            editor_id: editor_id.map(|x| x.to_owned()),
+            edited_at: Utc::now().timestamp(),
+            challenge,
+            changes,
+            rolled_back_to,
+        };
+        storage
+            .put(&history_key(challenge_id, version), &record)
+            .await?;
+        storage.put(&latest_key, version).await?;
 
-    Ok(challenge)
-}
+        Ok(())
+    }
 
-pub async fn update_challenge(challenges: &Challenges, mut req: Request) -> ApiResult<Challenge> {
-    let dto = req.parse_json::<UpdateChallengeDto>().await?;
-    let mut challenge = challenges.get_by_id(&dto.id).await?;
-    challenge.update(&dto);
+    /// Versions of the challenge, the latest first. It is kept after the challenge is deleted.
+    pub async fn list_history(&self, challenge_id: &str) -> ApiResult<Vec<ChallengeVersion>> {
+        let prefix = history_prefix(challenge_id);
+        let options = ListOptions::new().prefix(&prefix);
+        let entries = self.state.storage().list_with_options(options).await?;
 
-    challenges.update(&challenge).await?;
+        let mut versions = Vec::<ChallengeVersion>::new();
+        entries.for_each(&mut |value, _| {
+            versions.push(value.into_serde::<ChallengeVersion>().unwrap());
+        });
+        versions.reverse();
 
-    Ok(challenge)
-}
+        Ok(versions)
+    }
 
-#[durable_object]
-impl DurableObject for Challenges {
-    fn new(state: State, env: Env) -> Self {
-        Self { state, env }
+    /// Stores the challenge as it was in `version` again, recorded as a new version.
+    pub async fn rollback(
+        &self,
+        challenge_id: &str,
+        version: u32,
+        editor_id: Option<&str>,
+    ) -> ApiResult<Challenge> {
+        let record = self
+            .state
+            .storage()
+            .find::<ChallengeVersion>(&history_key(challenge_id, version))
+            .await?;
+        let challenge = match record {
+            Some(ChallengeVersion {
+                challenge: Some(challenge),
+                ..
+            }) => challenge,
+            Some(_) => {
+                return Err(ApiError::BadRequest(format!(
+                    "version {} deleted the challenge",
+                    version
+                )))
+            }
+            None => return Err(ApiError::ChallengeVersionNotExists),
+        };
+
+        let taken_stamp_ids = self.stamp_ids_except(challenge_id).await?;
+        challenge.validate(&taken_stamp_ids)?;
+        self.store(&challenge, editor_id, Some(version)).await?;
+
+        Ok(challenge)
     }
 
-    async fn fetch(&mut self, req: Request) -> Result<Response> {
-        let method = req.method();
-        let path = req.path();
+    /// Ids of every stamp owned by challenges other than `challenge_id`, archived ones included.
+    pub async fn stamp_ids_except(&self, challenge_id: &str) -> ApiResult<Vec<String>> {
+        let stamp_ids = self
+            .list(&ChallengeFilter::all())
+            .await?
+            .iter()
+            .filter(|x| x.id != challenge_id)
+            .flat_map(|x| x.stamp_ids())
+            .collect();
 
-        // GET /challenges
-        if method == Method::Get && &path == "/challenges" {
-            return match self.list().await {
-                Ok(challenges) => response_with_cache(&json!({ "challenges": challenges }), 60),
-                Err(e) => Ok(e.to_response()),
-            };
-        }
+        Ok(stamp_ids)
+    }
 
-        // POST /challenges
-        if method == Method::Post && &path == "/challenges" {
-            return match create_challenge(self, req).await {
-                Ok(challenge) => response(&challenge),
-                Err(e) => Ok(e.to_response()),
-            };
+    /// The sort order placing a new challenge after every other one.
+    pub async fn next_sort_order(&self) -> ApiResult<i64> {
+        let last = self
+            .list(&ChallengeFilter::all())
+            .await?
+            .iter()
+            .map(|x| x.sort_order)
+            .max();
+
+        Ok(sort_order_between(last, None).unwrap_or_default())
+    }
+
+    /// Moves the challenge right after `after_id`, or first when it is `None`. Only the moved
+    /// challenge is rewritten, unless its neighbours left no room and all of them are renumbered.
+    /// Either way, only the moved challenge gets a version in its history.
+    pub async fn move_after(
+        &self,
+        id: &str,
+        after_id: Option<&str>,
+        editor_id: Option<&str>,
+    ) -> ApiResult<Challenge> {
+        let mut list = self.list(&ChallengeFilter::all()).await?;
+        let sort = ChallengeSort::default();
+        list.sort_by(|a, b| sort.compare(a, b));
+
+        let index = match list.iter().position(|x| x.id == id) {
+            Some(index) => index,
+            None => return Err(ApiError::ChallengeNotExists),
+        };
+        let mut challenge = list.remove(index);
+        let insert_at = match after_id {
+            Some(after_id) => match list.iter().position(|x| x.id == after_id) {
+                Some(index) => index + 1,
+                None => return Err(ApiError::ChallengeNotExists),
+            },
+            None => 0,
+        };
+
+        let previous = insert_at.checked_sub(1).map(|x| list[x].sort_order);
+        let next = list.get(insert_at).map(|x| x.sort_order);
+        if let Some(sort_order) = sort_order_between(previous, next) {
+            challenge.sort_order = sort_order;
+            self.update(&challenge, editor_id).await?;
+
+            return Ok(challenge);
         }
 
-        // PUT /challenges
-        if method == Method::Put && &path == "/challenges" {
-            return match update_challenge(self, req).await {
-                Ok(challenge) => response(&challenge),
-                Err(e) => Ok(e.to_response()),
-            };
+        // note: renumbering doesn't edit the other challenges, so it stays out of their history.
+        list.insert(insert_at, challenge);
+        let mut storage = self.state.storage();
+        for (index, x) in list.iter_mut().enumerate() {
+            x.sort_order = (index as i64 + 1) * SORT_ORDER_GAP;
+            if index != insert_at {
+                storage.put(&x.id_key(), &x).await?;
+            }
         }
 
-        Response::error("not found", 404)
+        let challenge = list.remove(insert_at);
+        self.update(&challenge, editor_id).await?;
+
+        Ok(challenge)
+    }
+
+    /// Validates the challenge against all the others and stores it.
+    pub async fn validate_and_update(
+        &self,
+        challenge: &Challenge,
+        editor_id: Option<&str>,
+    ) -> ApiResult<()> {
+        let taken_stamp_ids = self.stamp_ids_except(&challenge.id).await?;
+        challenge.validate(&taken_stamp_ids)?;
+
+        self.update(challenge, editor_id).await
+    }
+
+    pub async fn count_participants(&self) -> ApiResult<HashMap<String, u32>> {
+        let options = ListOptions::new().prefix(PARTICIPANT_COUNT_PREFIX);
+        let entries = self.state.storage().list_with_options(options).await?;
+
+        let mut counts = HashMap::<String, u32>::new();
+        entries.for_each(&mut |value, key| {
+            let key = key.as_string().unwrap();
+            let challenge_id = key.trim_start_matches(PARTICIPANT_COUNT_PREFIX);
+            counts.insert(challenge_id.to_owned(), value.into_serde::<u32>().unwrap());
+        });
+
+        Ok(counts)
+    }
+
+    pub async fn list_joined_ids(&self, user_id: &str) -> ApiResult<Vec<String>> {
+        let prefix = joined_prefix(user_id);
+        let options = ListOptions::new().prefix(&prefix);
+        let entries = self.state.storage().list_with_options(options).await?;
+
+        let mut ids = Vec::<String>::new();
+        entries.for_each(&mut |_, key| {
+            let key = key.as_string().unwrap();
+            ids.push(key.trim_start_matches(&prefix).to_owned());
+        });
+
+        Ok(ids)
+    }
+
+    /// Attaches participant counts, and the `joined` flag when `user_id` is given.
+    pub async fn to_dtos(
+        &self,
+        challenges: Vec<Challenge>,
+        user_id: Option<&str>,
+    ) -> ApiResult<Vec<ChallengeDto>> {
+        let counts = self.count_participants().await?;
+        let joined_ids = match user_id {
+            Some(user_id) => Some(self.list_joined_ids(user_id).await?),
+            None => None,
+        };
+
+        let dtos = challenges
+            .into_iter()
+            .map(|challenge| ChallengeDto {
+                participant_count: counts.get(&challenge.id).copied().unwrap_or(0),
+                joined: joined_ids.as_ref().map(|ids| ids.contains(&challenge.id)),
+                challenge,
+            })
+            .collect();
+
+        Ok(dtos)
+    }
+
+    /// Joins the user to the challenge. Joining twice changes nothing.
+    pub async fn join(&self, challenge: &Challenge, user_id: &str) -> ApiResult<()> {
+        let mut storage = self.state.storage();
+        let key = joined_key(user_id, &challenge.id);
+
+        if storage.find::<i64>(&key).await?.is_some() {
+            return Ok(());
+        }
+
+        let count_key = participant_count_key(&challenge.id);
+        let count = storage.find::<u32>(&count_key).await?.unwrap_or(0);
+
+        storage.put(&key, Utc::now().timestamp()).await?;
+        storage.put(&count_key, count + 1).await?;
+
+        Ok(())
+    }
+
+    /// Removes the user from the challenge. Leaving a challenge not joined changes nothing.
+    pub async fn leave(&self, challenge: &Challenge, user_id: &str) -> ApiResult<()> {
+        let mut storage = self.state.storage();
+        let key = joined_key(user_id, &challenge.id);
+
+        if storage.find::<i64>(&key).await?.is_none() {
+            return Ok(());
+        }
+
+        let count_key = participant_count_key(&challenge.id);
+        let count = storage.find::<u32>(&count_key).await?.unwrap_or(0);
+
+        storage.delete(&key).await?;
+        storage.put(&count_key, count.saturating_sub(1)).await?;
+
+        Ok(())
+    }
+
+    pub async fn delete(&self, challenge: &Challenge, editor_id: Option<&str>) -> ApiResult<()> {
+        self.state.storage().delete(&challenge.id_key()).await?;
+
+        self.add_version(&challenge.id, None, vec![], editor_id, None)
+            .await
+    }
+
+    /// Brings the badges and ranks of foodnote authors up to date with the challenges that switched
+    /// on or off since the last check. A check is only recorded once foodnotes are refreshed, and
+    /// the first one records `now` alone.
+    pub async fn apply_schedule_transitions(&self, now: i64) -> ApiResult<Vec<Challenge>> {
+        let mut storage = self.state.storage();
+        let switched = match storage.find::<i64>(SCHEDULE_CHECKED_AT_KEY).await? {
+            Some(checked_at) => self
+                .list(&ChallengeFilter::all())
+                .await?
+                .into_iter()
+                .filter(|x| x.switches_between(checked_at, now))
+                .collect(),
+            None => vec![],
+        };
+
+        if !switched.is_empty() {
+            self.refresh_foodnotes(&switched).await?;
+        }
+        storage.put(SCHEDULE_CHECKED_AT_KEY, now).await?;
+
+        Ok(switched)
+    }
+
+    async fn refresh_foodnotes(&self, challenges: &[Challenge]) -> ApiResult<()> {
+        let stub = get_durable_stub(&self.env, "FOODNOTES")?;
+        let dto = RefreshChallengesDto {
+            challenges: challenges.to_vec(),
+        };
+        fetch_internal::<_, serde_json::Value>(
+            &stub,
+            Method::Post,
+            "/internal/challenges/refresh",
+            Some(&dto),
+        )
+        .await?;
+
+        Ok(())
+    }
+
+    async fn is_referenced_by_foodnotes(&self, challenge: &Challenge) -> ApiResult<bool> {
+        let referenced = self.list_referenced_stamps(challenge.stamp_ids()).await?;
+
+        Ok(!referenced.is_empty())
+    }
+
+    /// The stamps among `stamp_ids` that foodnotes claim.
+    pub async fn list_referenced_stamps(&self, stamp_ids: Vec<String>) -> ApiResult<Vec<String>> {
+        if stamp_ids.is_empty() {
+            return Ok(stamp_ids);
+        }
+
+        let stub = get_durable_stub(&self.env, "FOODNOTES")?;
+        let dto = StampReferencesDto { stamp_ids };
+        let res = fetch_internal::<_, StampReferencesDto>(
+            &stub,
+            Method::Post,
+            "/internal/stamps/references",
+            Some(&dto),
+        )
+        .await?;
+
+        Ok(res.stamp_ids)
+    }
+
+    /// Rejects the edit of a challenge from `before` to `after` when it removes stamps foodnotes
+    /// still claim.
+    pub async fn check_removed_stamps(
+        &self,
+        before: &Challenge,
+        after: &Challenge,
+    ) -> ApiResult<()> {
+        let removed = removed_stamp_ids(before, after);
+        let referenced = self.list_referenced_stamps(removed).await?;
+
+        check_stamp_removal(before, after, &referenced)
+    }
+}
+
+pub fn is_admin_view(req: &Request) -> ApiResult<bool> {
+    Ok(ChallengeFilter::from_req(req)?.is_admin_view())
+}
+
+pub async fn create_challenge(
+    challenges: &Challenges,
+    mut req: Request,
+    editor_id: Option<&str>,
+) -> ApiResult<Challenge> {
+    let dto = req.parse_json::<CreateChallengeDto>().await?;
+    let mut challenge = Challenge::new(&dto);
+    challenge.sort_order = challenges.next_sort_order().await?;
+
+    challenges
+        .validate_and_update(&challenge, editor_id)
+        .await?;
+
+    Ok(challenge)
+}
+
+pub async fn update_challenge(
+    challenges: &Challenges,
+    mut req: Request,
+    editor_id: Option<&str>,
+) -> ApiResult<Challenge> {
+    let dto = req.parse_json::<UpdateChallengeDto>().await?;
+    let mut challenge = challenges.get_by_id(&dto.id).await?;
+    let before = challenge.clone();
+    challenge.update(&dto);
+
+    challenges.check_removed_stamps(&before, &challenge).await?;
+    challenges
+        .validate_and_update(&challenge, editor_id)
+        .await?;
+
+    Ok(challenge)
+}
+
+/// Exports every challenge, archived ones included, in the admin order.
+pub async fn export_challenges(challenges: &Challenges, req: &Request) -> ApiResult<Response> {
+    let format = BulkFormat::parse(req.query_param("format")?.as_deref())?;
+    let mut list = challenges.list(&ChallengeFilter::all()).await?;
+    let sort = ChallengeSort::default();
+    list.sort_by(|a, b| sort.compare(a, b));
+
+    match format {
+        BulkFormat::Json => Ok(response(&json!({ "challenges": list }))?),
+        BulkFormat::Csv => {
+            let mut res = Response::ok(to_csv(&list)?)?;
+            res.headers_mut()
+                .set("content-type", "text/csv; charset=utf-8")?;
+            res.headers_mut().set(
+                "content-disposition",
+                "attachment; filename=\"challenges.csv\"",
+            )?;
+
+            Ok(res)
+        }
+    }
+}
+
+/// Upserts challenges from JSON or CSV. Nothing is written on `?dry_run=true`, nor when any of them
+/// is invalid; the report tells what changes, or would change, for every challenge.
+pub async fn import_challenges(
+    challenges: &Challenges,
+    mut req: Request,
+    editor_id: Option<&str>,
+) -> ApiResult<ImportReport> {
+    let format = BulkFormat::parse(req.query_param("format")?.as_deref())?;
+    let dry_run = req.query_param("dry_run")?.as_deref() == Some("true");
+    let items = match format {
+        BulkFormat::Json => {
+            ImportItem::from_dtos(req.parse_json::<ImportChallengesDto>().await?.challenges)
+        }
+        BulkFormat::Csv => from_csv(&req.text().await?)?,
+    };
+
+    let existing = challenges.list(&ChallengeFilter::all()).await?;
+    let last_sort_order = existing
+        .iter()
+        .map(|x| x.sort_order)
+        .max()
+        .unwrap_or_default();
+    let (planned, mut rows) = plan_import(&existing, &items, last_sort_order);
+
+    let before_of = |x: &Challenge| existing.iter().find(|before| before.id == x.id);
+    let removed = planned
+        .iter()
+        .filter_map(|x| Some(removed_stamp_ids(before_of(x)?, x)))
+        .flatten()
+        .collect();
+    let referenced = challenges.list_referenced_stamps(removed).await?;
+    for (after, row) in planned.iter().zip(rows.iter_mut()) {
+        let checked = match before_of(after) {
+            Some(before) => check_stamp_removal(before, after, &referenced),
+            None => Ok(()),
+        };
+        if let Err(ApiError::InvalidFields(mut fields)) = checked {
+            row.errors.append(&mut fields);
+        }
+    }
+    let report = ImportReport::new(rows, dry_run);
+
+    if report.applied {
+        for (challenge, row) in planned.iter().zip(&report.rows) {
+            if row.action != ImportAction::Unchanged {
+                challenges.update(challenge, editor_id).await?;
+            }
+        }
+    }
+
+    Ok(report)
+}
+
+pub async fn move_challenge(
+    challenges: &Challenges,
+    id: &str,
+    mut req: Request,
+    editor_id: Option<&str>,
+) -> ApiResult<Challenge> {
+    let dto = req.parse_json::<MoveChallengeDto>().await?;
+
+    challenges
+        .move_after(id, dto.after_id.as_deref(), editor_id)
+        .await
+}
+
+pub async fn add_stamp(
+    challenges: &Challenges,
+    id: &str,
+    mut req: Request,
+    editor_id: Option<&str>,
+) -> ApiResult<Stamp> {
+    let stamp = req.parse_json::<Stamp>().await?;
+    let mut challenge = challenges.get_by_id(id).await?;
+    challenge.stamps.push(stamp.clone());
+
+    challenges
+        .validate_and_update(&challenge, editor_id)
+        .await?;
+
+    Ok(stamp)
+}
+
+pub async fn update_stamp(
+    challenges: &Challenges,
+    id: &str,
+    stamp_id: &str,
+    mut req: Request,
+    editor_id: Option<&str>,
+) -> ApiResult<Stamp> {
+    let dto = req.parse_json::<UpdateStampDto>().await?;
+    let mut challenge = challenges.get_by_id(id).await?;
+    let stamp = challenge.update_stamp(stamp_id, &dto)?.clone();
+
+    challenges
+        .validate_and_update(&challenge, editor_id)
+        .await?;
+
+    Ok(stamp)
+}
+
+pub async fn reorder_stamps(
+    challenges: &Challenges,
+    id: &str,
+    mut req: Request,
+    editor_id: Option<&str>,
+) -> ApiResult<Challenge> {
+    let dto = req.parse_json::<ReorderStampsDto>().await?;
+    let mut challenge = challenges.get_by_id(id).await?;
+    challenge.reorder_stamps(&dto.stamp_ids)?;
+
+    challenges.update(&challenge, editor_id).await?;
+
+    Ok(challenge)
+}
+
+pub async fn issue_claim_code(
+    challenges: &Challenges,
+    id: &str,
+    stamp_id: &str,
+    mut req: Request,
+) -> ApiResult<ClaimCodeDto> {
+    let dto = req.parse_json::<CreateClaimCodeDto>().await?;
+    let challenge = challenges.get_by_id(id).await?;
+    let stamp = challenge.get_stamp(stamp_id)?;
+
+    let now = Utc::now().timestamp();
+    let claim_code = ClaimCode::new(&stamp.id, &dto);
+    claim_code.validate(now)?;
+    // note: a code is never issued for a place the stamp can't be collected at, whatever the
+    // enforcement, as redeeming it vouches for the visit.
+    if let Some(constraints) = &stamp.constraints {
+        constraints.verify(&claim_code.place)?;
+    }
+
+    let jwt = get_jwt_for_claim_codes(&challenges.env)?;
+    let code = sign_claim_code(&jwt, &claim_code, now)?;
+
+    Ok(ClaimCodeDto { code, claim_code })
+}
+
+pub async fn remove_stamp(
+    challenges: &Challenges,
+    id: &str,
+    stamp_id: &str,
+    editor_id: Option<&str>,
+) -> ApiResult<Stamp> {
+    let mut challenge = challenges.get_by_id(id).await?;
+    let before = challenge.clone();
+    let stamp = challenge.remove_stamp(stamp_id)?;
+
+    challenges.check_removed_stamps(&before, &challenge).await?;
+    challenges.update(&challenge, editor_id).await?;
+
+    Ok(stamp)
+}
+
+/// Archives the challenge when foodnotes still reference one of its stamps, otherwise removes it.
+/// Returns the archived challenge, or `None` when it was removed.
+pub async fn delete_challenge(
+    challenges: &Challenges,
+    id: &str,
+    editor_id: Option<&str>,
+) -> ApiResult<Option<Challenge>> {
+    let mut challenge = challenges.get_by_id(id).await?;
+
+    if challenges.is_referenced_by_foodnotes(&challenge).await? {
+        challenge.archive();
+        challenges.update(&challenge, editor_id).await?;
+
+        return Ok(Some(challenge));
+    }
+
+    challenges.delete(&challenge, editor_id).await?;
+
+    Ok(None)
+}
+
+/// Lists challenges localized to `languages`, or with every translation when it is `None`.
+pub async fn list_challenges(
+    challenges: &Challenges,
+    filter: &ChallengeFilter,
+    sort: &ChallengeSort,
+    user_id: Option<&str>,
+    languages: Option<&[String]>,
+) -> ApiResult<Vec<ChallengeDto>> {
+    let mut list = challenges.list(filter).await?;
+    if let Some(languages) = languages {
+        list.iter_mut().for_each(|x| {
+            x.localize(languages);
+        });
+    }
+    list.sort_by(|a, b| sort.compare(a, b));
+
+    challenges.to_dtos(list, user_id).await
+}
+
+pub async fn get_challenge(
+    challenges: &Challenges,
+    id: &str,
+    user_id: Option<&str>,
+    languages: &[String],
+    is_admin_view: bool,
+) -> ApiResult<ChallengeDto> {
+    let challenge = challenges.get_visible_by_id(id, is_admin_view).await?;
+    // note: admins edit challenges as they are stored, in every language.
+    let languages = match is_admin_view {
+        true => None,
+        false => Some(languages),
+    };
+
+    to_challenge_dto(challenges, challenge, user_id, languages).await
+}
+
+async fn to_challenge_dto(
+    challenges: &Challenges,
+    mut challenge: Challenge,
+    user_id: Option<&str>,
+    languages: Option<&[String]>,
+) -> ApiResult<ChallengeDto> {
+    if let Some(languages) = languages {
+        challenge.localize(languages);
+    }
+    let mut dtos = challenges.to_dtos(vec![challenge], user_id).await?;
+
+    Ok(dtos.remove(0))
+}
+
+pub async fn join_challenge(
+    challenges: &Challenges,
+    id: &str,
+    req: Request,
+) -> ApiResult<ChallengeDto> {
+    let user_id = get_identity(&challenges.env, &req)?.user_id;
+    let challenge = challenges.get_visible_by_id(id, false).await?;
+
+    challenges.join(&challenge, &user_id).await?;
+
+    let languages = preferred_languages(&req)?;
+
+    to_challenge_dto(challenges, challenge, Some(&user_id), Some(&languages)).await
+}
+
+pub async fn leave_challenge(
+    challenges: &Challenges,
+    id: &str,
+    req: Request,
+) -> ApiResult<ChallengeDto> {
+    let user_id = get_identity(&challenges.env, &req)?.user_id;
+    let challenge = challenges.get_by_id(id).await?;
+
+    challenges.leave(&challenge, &user_id).await?;
+
+    let languages = preferred_languages(&req)?;
+
+    to_challenge_dto(challenges, challenge, Some(&user_id), Some(&languages)).await
+}
+
+pub async fn restore_challenge(
+    challenges: &Challenges,
+    id: &str,
+    editor_id: Option<&str>,
+) -> ApiResult<Challenge> {
+    let mut challenge = challenges.get_by_id(id).await?;
+    challenge.restore();
+
+    challenges.update(&challenge, editor_id).await?;
+
+    Ok(challenge)
+}
+
+/// Versions of the challenge, the latest first. Challenges stored before the history was kept
+/// have none until their next change.
+pub async fn get_challenge_history(
+    challenges: &Challenges,
+    id: &str,
+) -> ApiResult<Vec<ChallengeVersion>> {
+    let history = challenges.list_history(id).await?;
+    if history.is_empty() {
+        challenges.get_by_id(id).await?;
+    }
+
+    Ok(history)
+}
+
+pub async fn rollback_challenge(
+    challenges: &Challenges,
+    id: &str,
+    mut req: Request,
+    editor_id: Option<&str>,
+) -> ApiResult<Challenge> {
+    let dto = req.parse_json::<RollbackDto>().await?;
+
+    challenges.rollback(id, dto.version, editor_id).await
+}
+
+#[durable_object]
+impl DurableObject for Challenges {
+    fn new(state: State, env: Env) -> Self {
+        Self { state, env }
+    }
+
+    async fn fetch(&mut self, req: Request) -> Result<Response> {
+        let method = req.method();
+        let path = req.path();
+        let segments = path_segments(&path);
+        let identity = match find_identity(&self.env, &req) {
+            Ok(identity) => identity,
+            Err(e) => return Ok(e.to_response()),
+        };
+        let is_admin = matches!(&identity, Some(x) if x.is_admin());
+        let is_admin_request =
+            is_admin_route(&method, &segments) || is_admin_view(&req).unwrap_or_default();
+        if is_admin_request && !is_admin {
+            return Ok(ApiError::Unauthorized.to_response());
+        }
+        let user_id = identity.as_ref().map(|x| x.user_id.as_str());
+        let editor_id = user_id;
+
+        // GET /challenges
+        if method == Method::Get && &path == "/challenges" {
+            let filter = ChallengeFilter::from_req(&req).unwrap_or_default();
+            let sort = match ChallengeSort::from_req(&req) {
+                Ok(sort) => sort,
+                Err(e) => return Ok(e.to_response()),
+            };
+            // note: admins get every translation to edit them.
+            let languages = preferred_languages(&req).unwrap_or_default();
+            let languages = match filter.is_admin_view() {
+                true => None,
+                false => Some(languages.as_slice()),
+            };
+
+            let list = list_challenges(self, &filter, &sort, user_id, languages);
+
+            return match list.await {
+                Ok(challenges) if filter.is_admin_view() || user_id.is_some() => {
+                    response(&json!({ "challenges": challenges }))
+                }
+                Ok(challenges) => {
+                    let now = Utc::now().timestamp();
+                    let max_age = cache_max_age(challenges.iter().map(|x| &x.challenge), now, 60);
+                    let mut res =
+                        response_with_cache(&json!({ "challenges": challenges }), max_age)?;
+                    res.headers_mut()
+                        .set("vary", "Authorization, Accept-Language")?;
+
+                    Ok(res)
+                }
+                Err(e) => Ok(e.to_response()),
+            };
+        }
+
+        // GET /challenges/export
+        if method == Method::Get && &path == "/challenges/export" {
+            return match export_challenges(self, &req).await {
+                Ok(res) => Ok(res),
+                Err(e) => Ok(e.to_response()),
+            };
+        }
+
+        // POST /challenges/import
+        if method == Method::Post && &path == "/challenges/import" {
+            return match import_challenges(self, req, editor_id).await {
+                Ok(report) if report.is_valid() => response(&report),
+                Ok(report) => Ok(response(&report)?.with_status(400)),
+                Err(e) => Ok(e.to_response()),
+            };
+        }
+
+        // GET /internal/challenges
+        if method == Method::Get && &path == "/internal/challenges" {
+            return match self.list(&ChallengeFilter::all()).await {
+                Ok(challenges) => response(&json!({ "challenges": challenges })),
+                Err(e) => Ok(e.to_response()),
+            };
+        }
+
+        // POST /internal/challenges/transitions
+        if method == Method::Post && &path == "/internal/challenges/transitions" {
+            return match self
+                .apply_schedule_transitions(Utc::now().timestamp())
+                .await
+            {
+                Ok(switched) => response(&json!({ "switched": switched.len() })),
+                Err(e) => Ok(e.to_response()),
+            };
+        }
+
+        // GET /challenges/:id
+        if let (Method::Get, ["challenges", id]) = (&method, segments.as_slice()) {
+            let languages = preferred_languages(&req).unwrap_or_default();
+            let admin_view = is_admin_view(&req).unwrap_or_default();
+
+            return match get_challenge(self, id, user_id, &languages, admin_view).await {
+                Ok(challenge) if user_id.is_some() || admin_view => response(&challenge),
+                Ok(challenge) => {
+                    let now = Utc::now().timestamp();
+                    let max_age = cache_max_age([&challenge.challenge], now, 60);
+                    let mut res = response_with_cache(&challenge, max_age)?;
+                    res.headers_mut()
+                        .set("vary", "Authorization, Accept-Language")?;
+
+                    Ok(res)
+                }
+                Err(e) => Ok(e.to_response()),
+            };
+        }
+
+        // GET /challenges/:id/stamps/:stamp_id
+        if let (Method::Get, ["challenges", id, "stamps", stamp_id]) =
+            (&method, segments.as_slice())
+        {
+            let languages = preferred_languages(&req).unwrap_or_default();
+            let admin_view = is_admin_view(&req).unwrap_or_default();
+
+            return match self.get_visible_by_id(id, admin_view).await {
+                Ok(mut challenge) => {
+                    let now = Utc::now().timestamp();
+                    let max_age = cache_max_age([&challenge], now, 60);
+
+                    // note: admins see the stored text with every translation, like the challenge.
+                    if admin_view {
+                        return match challenge.get_stamp(stamp_id) {
+                            Ok(stamp) => response(stamp),
+                            Err(e) => Ok(e.to_response()),
+                        };
+                    }
+
+                    match challenge.localize(&languages).get_stamp(stamp_id) {
+                        Ok(stamp) => {
+                            let mut res = response_with_cache(stamp, max_age)?;
+                            res.headers_mut().set("vary", "Accept-Language")?;
+
+                            Ok(res)
+                        }
+                        Err(e) => Ok(e.to_response()),
+                    }
+                }
+                Err(e) => Ok(e.to_response()),
+            };
+        }
+
+        // POST /challenges
+        if method == Method::Post && &path == "/challenges" {
+            return match create_challenge(self, req, editor_id).await {
+                Ok(challenge) => response(&challenge),
+                Err(e) => Ok(e.to_response()),
+            };
+        }
+
+        // PUT /challenges
+        if method == Method::Put && &path == "/challenges" {
+            return match update_challenge(self, req, editor_id).await {
+                Ok(challenge) => response(&challenge),
+                Err(e) => Ok(e.to_response()),
+            };
+        }
+
+        // DELETE /challenges/:id
+        if let (Method::Delete, ["challenges", id]) = (&method, segments.as_slice()) {
+            return match delete_challenge(self, id, editor_id).await {
+                Ok(archived) => response(&json!({
+                    "id": id,
+                    "archived": archived.is_some(),
+                    "challenge": archived,
+                })),
+                Err(e) => Ok(e.to_response()),
+            };
+        }
+
+        // POST /challenges/:id/join
+        if let (Method::Post, ["challenges", id, "join"]) = (&method, segments.as_slice()) {
+            return match join_challenge(self, id, req).await {
+                Ok(challenge) => response(&challenge),
+                Err(e) => Ok(e.to_response()),
+            };
+        }
+
+        // DELETE /challenges/:id/join
+        if let (Method::Delete, ["challenges", id, "join"]) = (&method, segments.as_slice()) {
+            return match leave_challenge(self, id, req).await {
+                Ok(challenge) => response(&challenge),
+                Err(e) => Ok(e.to_response()),
+            };
+        }
+
+        // PUT /challenges/:id/position
+        if let (Method::Put, ["challenges", id, "position"]) = (&method, segments.as_slice()) {
+            return match move_challenge(self, id, req, editor_id).await {
+                Ok(challenge) => response(&challenge),
+                Err(e) => Ok(e.to_response()),
+            };
+        }
+
+        // GET /challenges/:id/history
+        if let (Method::Get, ["challenges", id, "history"]) = (&method, segments.as_slice()) {
+            return match get_challenge_history(self, id).await {
+                Ok(history) => response(&json!({ "history": history })),
+                Err(e) => Ok(e.to_response()),
+            };
+        }
+
+        // POST /challenges/:id/rollback
+        if let (Method::Post, ["challenges", id, "rollback"]) = (&method, segments.as_slice()) {
+            return match rollback_challenge(self, id, req, editor_id).await {
+                Ok(challenge) => response(&challenge),
+                Err(e) => Ok(e.to_response()),
+            };
+        }
+
+        // POST /challenges/:id/restore
+        if let (Method::Post, ["challenges", id, "restore"]) = (&method, segments.as_slice()) {
+            return match restore_challenge(self, id, editor_id).await {
+                Ok(challenge) => response(&challenge),
+                Err(e) => Ok(e.to_response()),
+            };
+        }
+
+        // POST /challenges/:id/stamps
+        if let (Method::Post, ["challenges", id, "stamps"]) = (&method, segments.as_slice()) {
+            return match add_stamp(self, id, req, editor_id).await {
+                Ok(stamp) => response(&stamp),
+                Err(e) => Ok(e.to_response()),
+            };
+        }
+
+        // PUT /challenges/:id/stamps
+        if let (Method::Put, ["challenges", id, "stamps"]) = (&method, segments.as_slice()) {
+            return match reorder_stamps(self, id, req, editor_id).await {
+                Ok(challenge) => response(&challenge),
+                Err(e) => Ok(e.to_response()),
+            };
+        }
+
+        // PATCH /challenges/:id/stamps/:stamp_id
+        if let (Method::Patch, ["challenges", id, "stamps", stamp_id]) =
+            (&method, segments.as_slice())
+        {
+            return match update_stamp(self, id, stamp_id, req, editor_id).await {
+                Ok(stamp) => response(&stamp),
+                Err(e) => Ok(e.to_response()),
+            };
+        }
+
+        // DELETE /challenges/:id/stamps/:stamp_id
+        if let (Method::Delete, ["challenges", id, "stamps", stamp_id]) =
+            (&method, segments.as_slice())
+        {
+            return match remove_stamp(self, id, stamp_id, editor_id).await {
+                Ok(stamp) => response(&stamp),
+                Err(e) => Ok(e.to_response()),
+            };
+        }
+
+        // POST /challenges/:id/stamps/:stamp_id/claim-codes
+        if let (Method::Post, ["challenges", id, "stamps", stamp_id, "claim-codes"]) =
+            (&method, segments.as_slice())
+        {
+            return match issue_claim_code(self, id, stamp_id, req).await {
+                Ok(claim_code) => response(&claim_code),
+                Err(e) => Ok(e.to_response()),
+            };
+        }
+
+        Response::error("not found", 404)
+    }
+}
+
+#[cfg(test)]
+mod challenge_tests {
+    use super::*;
+
+    fn stamp(id: &str) -> Stamp {
+        Stamp {
+            id: id.to_string(),
+            title: "등심".to_string(),
+            title_translations: Translations::new(),
+            description: "육즙 가득".to_string(),
+            description_translations: Translations::new(),
+            img_url: "".to_string(),
+            constraints: None,
+        }
+    }
+
+    fn new_challenge(name: &str, stamps: Vec<Stamp>) -> Challenge {
+        Challenge::new(&CreateChallengeDto {
+            name: name.to_string(),
+            name_translations: Translations::new(),
+            stamps,
+            status: ChallengeStatus::Published,
+            starts_at: None,
+            ends_at: None,
+            cover_img_url: "".to_string(),
+            tags: vec![],
+            difficulty: None,
+            points: 0,
+        })
+    }
+
+    #[test]
+    fn should_get_stamp_by_id() {
+        let challenge = new_challenge("한우", vec![stamp("ribeye_roll"), stamp("sirloin")]);
+
+        assert_eq!(challenge.get_stamp("sirloin").unwrap().id, "sirloin");
+    }
+
+    #[test]
+    fn should_archive_and_restore_challenge() {
+        let mut challenge = new_challenge("한우", vec![stamp("ribeye_roll")]);
+        assert!(!challenge.is_archived());
+
+        challenge.archive();
+        assert!(challenge.is_archived());
+
+        challenge.restore();
+        assert!(!challenge.is_archived());
+    }
+
+    #[test]
+    fn should_read_stored_challenge_without_archived_at() {
+        let challenge =
+            serde_json::from_str::<Challenge>(r#"{"id":"a","name":"한우","stamps":[]}"#).unwrap();
+
+        assert!(!challenge.is_archived());
+    }
+
+    #[test]
+    fn should_validate_challenge() {
+        let challenge = new_challenge("한우", vec![stamp("ribeye_roll"), stamp("sirloin")]);
+
+        assert!(challenge.validate(&[]).is_ok());
+    }
+
+    #[test]
+    fn should_err_with_field_errors_when_invalid() {
+        let mut invalid = stamp("sirloin");
+        invalid.title = " ".to_string();
+        invalid.img_url = "not a url".to_string();
+        let challenge = new_challenge("", vec![stamp("ribeye_roll"), invalid]);
+
+        match challenge.validate(&[]).unwrap_err() {
+            ApiError::InvalidFields(errors) => assert_eq!(
+                errors,
+                vec![
+                    FieldError::new("name", "required"),
+                    FieldError::new("stamps[1].title", "required"),
+                    FieldError::new("stamps[1].img_url", "invalid url"),
+                ]
+            ),
+            e => panic!("unexpected error: {:?}", e),
+        }
+    }
+
+    #[test]
+    fn should_err_when_stamp_id_is_duplicated() {
+        let challenge = new_challenge(
+            "한우",
+            vec![stamp("ribeye_roll"), stamp("sirloin"), stamp("ribeye_roll")],
+        );
+        let taken = vec!["sirloin".to_string()];
+
+        match challenge.validate(&taken).unwrap_err() {
+            ApiError::InvalidFields(errors) => assert_eq!(
+                errors,
+                vec![
+                    FieldError::new("stamps[1].id", "duplicated"),
+                    FieldError::new("stamps[2].id", "duplicated"),
+                ]
+            ),
+            e => panic!("unexpected error: {:?}", e),
+        }
+    }
+
+    #[test]
+    fn should_update_and_remove_stamp() {
+        let mut challenge = new_challenge("한우", vec![stamp("ribeye_roll"), stamp("sirloin")]);
+        let updates = UpdateStampDto {
+            title: Some("채끝".to_string()),
+            title_translations: None,
+            description: None,
+            description_translations: None,
+            img_url: None,
+            constraints: None,
+        };
+
+        assert_eq!(
+            challenge.update_stamp("sirloin", &updates).unwrap().title,
+            "채끝"
+        );
+        assert_eq!(
+            challenge.remove_stamp("ribeye_roll").unwrap().id,
+            "ribeye_roll"
+        );
+        assert_eq!(challenge.stamp_ids(), vec!["sirloin"]);
+        assert!(matches!(
+            challenge.remove_stamp("ribeye_roll").unwrap_err(),
+            ApiError::StampNotExists
+        ));
+    }
+
+    #[test]
+    fn should_localize_challenge_and_stamps() {
+        let mut challenge = new_challenge("한우", vec![stamp("sirloin")]);
+        challenge
+            .name_translations
+            .insert("en".to_string(), "Hanwoo".to_string());
+        challenge.stamps[0]
+            .title_translations
+            .insert("en".to_string(), "Sirloin".to_string());
+
+        challenge.localize(&["en-us".to_string()]);
+
+        assert_eq!(challenge.name, "Hanwoo");
+        assert_eq!(challenge.stamps[0].title, "Sirloin");
+        assert_eq!(challenge.stamps[0].description, "육즙 가득");
+
+        let json = serde_json::to_value(&challenge).unwrap();
+        assert_eq!(json["name"], "Hanwoo");
+        assert!(json.get("name_translations").is_none());
+    }
+
+    #[test]
+    fn should_err_with_invalid_translations() {
+        let mut challenge = new_challenge("한우", vec![stamp("sirloin")]);
+        challenge.stamps[0]
+            .title_translations
+            .insert("en".to_string(), "".to_string());
+
+        match challenge.validate(&[]).unwrap_err() {
+            ApiError::InvalidFields(fields) => assert_eq!(
+                fields,
+                vec![FieldError::new(
+                    "stamps[0].title_translations.en",
+                    "required"
+                )]
+            ),
+            e => panic!("unexpected error: {:?}", e),
+        }
+    }
+
+    #[test]
+    fn should_reorder_stamps() {
+        let mut challenge = new_challenge("한우", vec![stamp("a"), stamp("b"), stamp("c")]);
+        let order = vec!["c".to_string(), "a".to_string(), "b".to_string()];
+
+        challenge.reorder_stamps(&order).unwrap();
+
+        assert_eq!(challenge.stamp_ids(), order);
+    }
+
+    #[test]
+    fn should_err_when_reorder_misses_stamps() {
+        let mut challenge = new_challenge("한우", vec![stamp("a"), stamp("b")]);
+
+        assert!(challenge.reorder_stamps(&["a".to_string()]).is_err());
+        assert!(challenge
+            .reorder_stamps(&["a".to_string(), "a".to_string()])
+            .is_err());
+    }
+
+    #[test]
+    fn should_be_active_only_while_published_and_scheduled() {
+        let mut challenge = new_challenge("냉면", vec![]);
+        challenge.starts_at = Some(100);
+        challenge.ends_at = Some(200);
+
+        assert!(!challenge.is_active_at(99));
+        assert!(challenge.is_active_at(100));
+        assert!(challenge.is_active_at(199));
+        assert!(!challenge.is_active_at(200));
+
+        challenge.status = ChallengeStatus::Draft;
+        assert!(!challenge.is_active_at(150));
+    }
+
+    #[test]
+    fn should_filter_challenges_for_admin_view() {
+        let mut draft = new_challenge("냉면", vec![]);
+        draft.status = ChallengeStatus::Draft;
+        let mut archived = new_challenge("한우", vec![]);
+        archived.archive();
+
+        let public = ChallengeFilter::default();
+        assert!(!public.matches(&draft, 0));
+        assert!(!public.matches(&archived, 0));
+
+        let inactive = ChallengeFilter {
+            include_archived: false,
+            include_inactive: true,
+            tags: vec![],
+        };
+        assert!(inactive.matches(&draft, 0));
+        assert!(!inactive.matches(&archived, 0));
+        assert!(ChallengeFilter::all().matches(&archived, 0));
+    }
+
+    #[test]
+    fn should_filter_challenges_by_tags() {
+        let mut challenge = new_challenge("냉면", vec![]);
+        challenge.tags = vec!["noodle".to_string(), "summer".to_string()];
+        let filter = |tags: &[&str]| ChallengeFilter {
+            tags: tags.iter().map(|x| x.to_string()).collect(),
+            ..ChallengeFilter::default()
+        };
+
+        assert!(filter(&[]).matches(&challenge, 0));
+        assert!(filter(&["beef", "summer"]).matches(&challenge, 0));
+        assert!(!filter(&["beef"]).matches(&challenge, 0));
+    }
+
+    #[test]
+    fn should_err_with_invalid_tags_and_cover() {
+        let mut challenge = new_challenge("냉면", vec![]);
+        challenge.cover_img_url = "cover.png".to_string();
+        challenge.tags = vec![
+            "noodle".to_string(),
+            "Noodle".to_string(),
+            "noodle".to_string(),
+        ];
+
+        match challenge.validate(&[]).unwrap_err() {
+            ApiError::InvalidFields(fields) => assert_eq!(
+                fields,
+                vec![
+                    FieldError::new("cover_img_url", "invalid url"),
+                    FieldError::new(
+                        "tags[1]",
+                        "only lowercase alphanumerics, '_' and '-' are allowed"
+                    ),
+                    FieldError::new("tags[2]", "duplicated"),
+                ]
+            ),
+            e => panic!("unexpected error: {:?}", e),
+        }
+    }
+
+    #[test]
+    fn should_sort_challenges() {
+        let mut easy = new_challenge("냉면", vec![]);
+        easy.difficulty = Some(ChallengeDifficulty::Easy);
+        easy.points = 30;
+        easy.sort_order = 2;
+        let mut hard = new_challenge("한우", vec![]);
+        hard.difficulty = Some(ChallengeDifficulty::Hard);
+        hard.points = 10;
+        hard.sort_order = 1;
+        let mut unrated = new_challenge("곱창", vec![]);
+        unrated.points = 20;
+        unrated.sort_order = 3;
+
+        let sorted = |sort: &str| {
+            let sort = ChallengeSort::parse(sort).unwrap();
+            let mut list = vec![easy.clone(), hard.clone(), unrated.clone()];
+            list.sort_by(|a, b| sort.compare(a, b));
+            list.into_iter().map(|x| x.name).collect::<Vec<String>>()
+        };
+
+        assert_eq!(sorted("order"), vec!["한우", "냉면", "곱창"]);
+        assert_eq!(sorted("-points"), vec!["냉면", "곱창", "한우"]);
+        assert_eq!(sorted("difficulty"), vec!["곱창", "냉면", "한우"]);
+        assert_eq!(sorted("name"), vec!["곱창", "냉면", "한우"]);
+        assert!(ChallengeSort::parse("created_at").is_err());
+    }
+
+    #[test]
+    fn should_find_sort_order_between_neighbours() {
+        assert_eq!(sort_order_between(None, None), Some(0));
+        assert_eq!(sort_order_between(Some(1024), None), Some(2048));
+        assert_eq!(sort_order_between(None, Some(1024)), Some(0));
+        assert_eq!(sort_order_between(Some(1024), Some(2048)), Some(1536));
+        assert_eq!(sort_order_between(Some(1024), Some(1025)), None);
+        assert_eq!(sort_order_between(Some(0), Some(0)), None);
+    }
+
+    #[test]
+    fn should_limit_cache_to_next_schedule_boundary() {
+        let mut starting = new_challenge("냉면", vec![]);
+        starting.starts_at = Some(1030);
+        let mut ending = new_challenge("한우", vec![]);
+        ending.ends_at = Some(1010);
+
+        assert_eq!(cache_max_age(&[], 1000, 60), 60);
+        assert_eq!(cache_max_age(&[starting.clone()], 1000, 60), 30);
+        assert_eq!(cache_max_age(&[starting, ending], 1000, 60), 10);
+    }
+
+    #[test]
+    fn should_switch_at_schedule_boundaries() {
+        let mut challenge = new_challenge("냉면", vec![]);
+        challenge.starts_at = Some(100);
+        challenge.ends_at = Some(200);
+
+        assert!(challenge.switches_between(90, 100));
+        assert!(!challenge.switches_between(100, 150));
+        assert!(challenge.switches_between(150, 250));
+        assert!(!challenge.switches_between(200, 300));
+        assert!(!new_challenge("한우", vec![]).switches_between(0, 1000));
+    }
+
+    #[test]
+    fn should_serialize_challenge_dto_flat() {
+        let challenge = new_challenge("냉면", vec![]);
+        let anonymous = ChallengeDto {
+            challenge: challenge.clone(),
+            participant_count: 3,
+            joined: None,
+        };
+        let personal = ChallengeDto {
+            challenge,
+            participant_count: 3,
+            joined: Some(true),
+        };
+
+        let anonymous = serde_json::to_value(&anonymous).unwrap();
+        let personal = serde_json::to_value(&personal).unwrap();
+
+        assert_eq!(anonymous["name"], "냉면");
+        assert_eq!(anonymous["participant_count"], 3);
+        assert!(anonymous.get("joined").is_none());
+        assert_eq!(personal["joined"], true);
+    }
+
+    #[test]
+    fn should_err_when_schedule_ends_before_start() {
+        let mut challenge = new_challenge("냉면", vec![]);
+        challenge.starts_at = Some(200);
+        challenge.ends_at = Some(100);
+
+        match challenge.validate(&[]).unwrap_err() {
+            ApiError::InvalidFields(errors) => assert_eq!(
+                errors,
+                vec![FieldError::new("ends_at", "must be after starts_at")]
+            ),
+            e => panic!("unexpected error: {:?}", e),
+        }
+    }
+
+    #[test]
+    fn should_clear_schedule_with_null() {
+        let mut challenge = new_challenge("냉면", vec![]);
+        challenge.starts_at = Some(100);
+        challenge.ends_at = Some(200);
+        let updates =
+            serde_json::from_str::<UpdateChallengeDto>(r#"{"id":"a","starts_at":null}"#).unwrap();
+
+        challenge.update(&updates);
+
+        assert_eq!(challenge.starts_at, None);
+        assert_eq!(challenge.ends_at, Some(200));
+    }
+
+    #[test]
+    fn should_err_when_stamp_not_exists() {
+        let challenge = new_challenge("한우", vec![stamp("ribeye_roll")]);
+        let err = challenge.get_stamp("unknown").unwrap_err();
+
+        assert!(matches!(err, ApiError::StampNotExists));
+    }
+
+    #[test]
+    fn should_err_when_removing_stamps_claimed_by_foodnotes() {
+        let before = new_challenge("한우", vec![stamp("ribeye_roll"), stamp("sirloin")]);
+        let mut after = before.clone();
+        after.remove_stamp("sirloin").unwrap();
+        let referenced = vec!["sirloin".to_string()];
+
+        assert_eq!(removed_stamp_ids(&before, &after), vec!["sirloin"]);
+        match check_stamp_removal(&before, &after, &referenced).unwrap_err() {
+            ApiError::InvalidFields(fields) => assert_eq!(fields[0].field, "stamps"),
+            e => panic!("unexpected error: {:?}", e),
+        }
+        assert!(check_stamp_removal(&before, &after, &[]).is_ok());
+        assert!(check_stamp_removal(&before, &before, &referenced).is_ok());
+    }
+
+    #[test]
+    fn should_find_stamps_of_active_challenges_only() {
+        let mut ended = new_challenge("냉면", vec![stamp("pyongyang")]);
+        ended.ends_at = Some(100);
+        let challenges = vec![new_challenge("한우", vec![stamp("sirloin")]), ended];
+
+        let found = find_active_stamp(&challenges, "sirloin", 150).unwrap();
+        assert_eq!(found.id, "sirloin");
+        assert!(matches!(
+            find_active_stamp(&challenges, "pyongyang", 150).unwrap_err(),
+            ApiError::StampNotExists
+        ));
+
+        let containing = challenges_with_stamps(&challenges, &["pyongyang", "unknown"]);
+        assert_eq!(containing.len(), 1);
+        assert_eq!(containing[0].name, "냉면");
     }
 }
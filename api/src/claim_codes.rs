@@ -0,0 +1,220 @@
+use chrono::Duration;
+use serde::{Deserialize, Serialize};
+use worker::Env;
+
+use crate::api_error::{ApiError, FieldError};
+use crate::api_result::ApiResult;
+use crate::jwt::Jwt;
+use crate::place::PlaceDocument;
+use crate::uid;
+
+const REDEMPTION_PREFIX: &str = "claim_";
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ClaimCodeUsage {
+    /// The code can be redeemed once, by whoever scans it first.
+    #[default]
+    Single,
+    /// The code can be redeemed once by every user, e.g. a QR code printed on a table.
+    Multi,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CreateClaimCodeDto {
+    /// The partner's place as found by the place search, checked against the stamp's constraints.
+    pub place: PlaceDocument,
+    pub starts_at: Option<i64>,
+    pub ends_at: i64,
+    #[serde(default)]
+    pub usage: ClaimCodeUsage,
+}
+
+/// What a claim code carries, signed so that partners can't award other stamps.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ClaimCode {
+    pub id: String,
+    pub stamp_id: String,
+    pub place: PlaceDocument,
+    pub starts_at: Option<i64>,
+    pub ends_at: i64,
+    pub usage: ClaimCodeUsage,
+}
+
+impl ClaimCode {
+    pub fn new(stamp_id: &str, dto: &CreateClaimCodeDto) -> Self {
+        Self {
+            id: uid!(),
+            stamp_id: stamp_id.to_owned(),
+            place: dto.place.clone(),
+            starts_at: dto.starts_at,
+            ends_at: dto.ends_at,
+            usage: dto.usage,
+        }
+    }
+
+    pub fn validate(&self, now: i64) -> ApiResult<()> {
+        let mut errors = Vec::<FieldError>::new();
+
+        if self.place.id.trim().is_empty() {
+            errors.push(FieldError::new("place.id", "required"));
+        }
+        if self.ends_at <= now {
+            errors.push(FieldError::new("ends_at", "must be in the future"));
+        }
+        if matches!(self.starts_at, Some(starts_at) if starts_at >= self.ends_at) {
+            errors.push(FieldError::new("ends_at", "must be after starts_at"));
+        }
+
+        match errors.is_empty() {
+            true => Ok(()),
+            false => Err(ApiError::InvalidFields(errors)),
+        }
+    }
+
+    /// Single-use codes are redeemed once in total, multi-use codes once per user.
+    pub fn redemption_key(&self, user_id: &str) -> String {
+        match self.usage {
+            ClaimCodeUsage::Single => format!("{}{}", REDEMPTION_PREFIX, self.id),
+            ClaimCodeUsage::Multi => format!("{}{}_{}", REDEMPTION_PREFIX, self.id, user_id),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ClaimCodeDto {
+    pub code: String,
+    pub claim_code: ClaimCode,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ClaimStampDto {
+    pub code: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Redemption {
+    pub user_id: String,
+    pub foodnote_id: String,
+    pub redeemed_at: i64,
+}
+
+pub fn get_jwt_for_claim_codes(env: &Env) -> ApiResult<Jwt> {
+    let secret = env.secret("CLAIM_CODE_SECRET")?;
+
+    Ok(Jwt::new(&secret.to_string()))
+}
+
+/// Signs the claim code, expiring it with the end of its validity window.
+pub fn sign_claim_code(jwt: &Jwt, claim_code: &ClaimCode, now: i64) -> ApiResult<String> {
+    let claims = jwt.create_claims(claim_code, Duration::seconds(claim_code.ends_at - now));
+
+    Ok(jwt.sign(&claims)?)
+}
+
+pub fn verify_claim_code(jwt: &Jwt, code: &str, now: i64) -> ApiResult<ClaimCode> {
+    let claim_code = match jwt.verify::<ClaimCode>(code) {
+        Ok(token) => token.claims().custom.clone(),
+        Err(_) => return Err(ApiError::InvalidClaimCode),
+    };
+
+    if matches!(claim_code.starts_at, Some(starts_at) if now < starts_at)
+        || claim_code.ends_at <= now
+    {
+        return Err(ApiError::InvalidClaimCode);
+    }
+
+    Ok(claim_code)
+}
+
+#[cfg(test)]
+mod claim_code_tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn place() -> PlaceDocument {
+        serde_json::from_value(json!({
+            "id": "234127678",
+            "place_name": "키친마이야르",
+            "category_name": "음식점 > 양식",
+            "category_group_code": "FD6",
+            "category_group_name": "음식점",
+            "phone": "02-511-7002",
+            "address_name": "서울 강남구 신사동 644-6",
+            "road_address_name": "서울 강남구 언주로170길 22",
+            "x": "127.035423195622",
+            "y": "37.5266092359544",
+            "place_url": "http://place.map.kakao.com/234127678",
+            "distance": ""
+        }))
+        .unwrap()
+    }
+
+    fn claim_code(usage: ClaimCodeUsage) -> ClaimCode {
+        ClaimCode::new(
+            "a",
+            &CreateClaimCodeDto {
+                place: place(),
+                starts_at: Some(100),
+                ends_at: 200,
+                usage,
+            },
+        )
+    }
+
+    #[test]
+    fn should_validate_claim_code() {
+        let claim_code = claim_code(ClaimCodeUsage::Single);
+        assert!(claim_code.validate(150).is_ok());
+
+        match claim_code.validate(300).unwrap_err() {
+            ApiError::InvalidFields(fields) => {
+                assert_eq!(
+                    fields,
+                    vec![FieldError::new("ends_at", "must be in the future")]
+                )
+            }
+            e => panic!("unexpected error: {:?}", e),
+        }
+    }
+
+    #[test]
+    fn should_verify_signed_claim_code_within_validity() {
+        let jwt = Jwt::new("this_is_secret");
+        let now = chrono::Utc::now().timestamp();
+        let mut claim_code = claim_code(ClaimCodeUsage::Single);
+        claim_code.starts_at = Some(now - 60);
+        claim_code.ends_at = now + 3600;
+
+        let code = sign_claim_code(&jwt, &claim_code, now).unwrap();
+
+        assert_eq!(verify_claim_code(&jwt, &code, now).unwrap(), claim_code);
+        assert!(matches!(
+            verify_claim_code(&jwt, &code, now - 120).unwrap_err(),
+            ApiError::InvalidClaimCode
+        ));
+    }
+
+    #[test]
+    fn should_not_verify_claim_code_with_other_secret() {
+        let now = chrono::Utc::now().timestamp();
+        let mut claim_code = claim_code(ClaimCodeUsage::Single);
+        claim_code.starts_at = None;
+        claim_code.ends_at = now + 3600;
+
+        let code = sign_claim_code(&Jwt::new("forged"), &claim_code, now).unwrap();
+        let verified = verify_claim_code(&Jwt::new("this_is_secret"), &code, now);
+
+        assert!(matches!(verified.unwrap_err(), ApiError::InvalidClaimCode));
+    }
+
+    #[test]
+    fn should_key_redemptions_by_usage() {
+        let single = claim_code(ClaimCodeUsage::Single);
+        let multi = claim_code(ClaimCodeUsage::Multi);
+
+        assert_eq!(single.redemption_key("alice"), single.redemption_key("bob"));
+        assert_ne!(multi.redemption_key("alice"), multi.redemption_key("bob"));
+    }
+}
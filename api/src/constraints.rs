@@ -0,0 +1,286 @@
+use serde::{Deserialize, Serialize};
+
+use crate::api_error::{ApiError, FieldError};
+use crate::api_result::ApiResult;
+use crate::geo::GeoPoint;
+use crate::place::PlaceDocument;
+
+/// How far from a place a check-in still counts as a visit, unless a stamp configures it.
+pub const DEFAULT_CHECK_IN_RADIUS_METERS: f64 = 100.0;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ConstraintEnforcement {
+    /// Foodnotes that don't match are refused.
+    #[default]
+    Reject,
+    /// Foodnotes that don't match are kept, but flagged and not counted toward progress.
+    Flag,
+}
+
+/// Which places a stamp can be collected at. Each non-empty list must be satisfied by one of its
+/// values, e.g. `category_group_codes: ["FD6"]` with `regions: ["서울 강남구"]` means a restaurant
+/// in Gangnam.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct PlaceConstraints {
+    #[serde(default)]
+    pub category_group_codes: Vec<String>,
+    /// Patterns over `category_name`, where `*` matches anything, e.g. `음식점 > 한식 > *`.
+    #[serde(default)]
+    pub category_name_patterns: Vec<String>,
+    /// Kakao place ids.
+    #[serde(default)]
+    pub place_ids: Vec<String>,
+    /// Leading parts of `address_name`, e.g. `서울` or `서울 강남구`.
+    #[serde(default)]
+    pub regions: Vec<String>,
+    #[serde(default)]
+    pub enforcement: ConstraintEnforcement,
+    /// Meters from the place within which a check-in verifies the visit.
+    pub check_in_radius: Option<f64>,
+    /// Only foodnotes with a verified visit count toward progress.
+    #[serde(default)]
+    pub verified_visit_required: bool,
+}
+
+impl PlaceConstraints {
+    /// Verifies the place following `enforcement`: returns whether a foodnote at the place must be
+    /// flagged, or the mismatch error when it must be rejected.
+    pub fn check(&self, place: &PlaceDocument) -> ApiResult<bool> {
+        match (self.verify(place), self.enforcement) {
+            (Ok(_), _) => Ok(false),
+            (Err(_), ConstraintEnforcement::Flag) => Ok(true),
+            (Err(e), ConstraintEnforcement::Reject) => Err(e),
+        }
+    }
+
+    pub fn validate(&self, field: &str) -> Vec<FieldError> {
+        let mut errors = Vec::<FieldError>::new();
+
+        if matches!(self.check_in_radius, Some(x) if !x.is_finite() || x <= 0.0) {
+            errors.push(FieldError::new(
+                &format!("{}.check_in_radius", field),
+                "must be a positive number",
+            ));
+        }
+
+        errors
+    }
+
+    pub fn check_in_radius(&self) -> f64 {
+        self.check_in_radius
+            .unwrap_or(DEFAULT_CHECK_IN_RADIUS_METERS)
+    }
+
+    pub fn verify(&self, place: &PlaceDocument) -> ApiResult<()> {
+        if !self.category_group_codes.is_empty()
+            && !self
+                .category_group_codes
+                .contains(&place.category_group_code)
+        {
+            return Err(mismatch("category_group_code", &self.category_group_codes));
+        }
+        if !self.category_name_patterns.is_empty()
+            && !self
+                .category_name_patterns
+                .iter()
+                .any(|x| matches_pattern(x, &place.category_name))
+        {
+            return Err(mismatch("category_name", &self.category_name_patterns));
+        }
+        if !self.place_ids.is_empty() && !self.place_ids.contains(&place.id) {
+            return Err(mismatch("id", &self.place_ids));
+        }
+        if !self.regions.is_empty()
+            && !self
+                .regions
+                .iter()
+                .any(|x| is_in_region(&place.address_name, x))
+        {
+            return Err(mismatch("address_name", &self.regions));
+        }
+
+        Ok(())
+    }
+}
+
+/// Whether the check-in was made within `radius` meters of the place.
+pub fn is_verified_visit(check_in: Option<&GeoPoint>, place: &PlaceDocument, radius: f64) -> bool {
+    match (check_in, GeoPoint::of_place(place)) {
+        (Some(check_in), Some(place)) => check_in.distance_to(&place) <= radius,
+        _ => false,
+    }
+}
+
+fn mismatch(field: &str, expected: &[String]) -> ApiError {
+    ApiError::PlaceMismatch(format!(
+        "place {} must match one of: {}",
+        field,
+        expected.join(", ")
+    ))
+}
+
+/// Matches `value` against `pattern`, where `*` matches any (possibly empty) text.
+pub fn matches_pattern(pattern: &str, value: &str) -> bool {
+    let parts = pattern.split('*').collect::<Vec<&str>>();
+    if parts.len() == 1 {
+        return pattern == value;
+    }
+
+    let first = parts[0];
+    let last = parts[parts.len() - 1];
+    // note: both ends must match before slicing between them, or it may cut through a character.
+    let fits = value.len() >= first.len() + last.len();
+    if !fits || !value.starts_with(first) || !value.ends_with(last) {
+        return false;
+    }
+
+    let mut rest = &value[first.len()..value.len() - last.len()];
+    for part in &parts[1..parts.len() - 1] {
+        match rest.find(part) {
+            Some(index) => rest = &rest[index + part.len()..],
+            None => return false,
+        }
+    }
+
+    true
+}
+
+/// Whether the address starts with every word of the region, e.g. `서울 강남구 신사동 644-6` is in
+/// `서울 강남구` but not in `서울 강북구`.
+pub fn is_in_region(address_name: &str, region: &str) -> bool {
+    let address = address_name.split_whitespace().collect::<Vec<&str>>();
+    let region = region.split_whitespace().collect::<Vec<&str>>();
+
+    !region.is_empty() && address.starts_with(&region)
+}
+
+#[cfg(test)]
+mod constraints_tests {
+    use super::*;
+    use crate::test_fixtures::place;
+
+    #[test]
+    fn should_match_patterns() {
+        assert!(matches_pattern("음식점 > 양식", "음식점 > 양식"));
+        assert!(matches_pattern("음식점 > *", "음식점 > 양식"));
+        assert!(matches_pattern("*양식*", "음식점 > 양식 > 스테이크"));
+        assert!(matches_pattern(
+            "음식점*스테이크",
+            "음식점 > 양식 > 스테이크"
+        ));
+        assert!(!matches_pattern("음식점 > 한식*", "음식점 > 양식"));
+        assert!(!matches_pattern("음식점*", "카페"));
+        assert!(!matches_pattern("ab*ba", "aba"));
+        assert!(!matches_pattern("음식점*a", "음식점 > 양식"));
+        assert!(!matches_pattern("*식*한식", "음식점 > 양식"));
+    }
+
+    #[test]
+    fn should_match_regions_by_leading_words() {
+        assert!(is_in_region("서울 강남구 신사동 644-6", "서울"));
+        assert!(is_in_region("서울 강남구 신사동 644-6", "서울 강남구"));
+        assert!(!is_in_region("서울 강남구 신사동 644-6", "서울 강북구"));
+        assert!(!is_in_region("서울특별시 강남구", "서울"));
+        assert!(!is_in_region("서울 강남구", " "));
+    }
+
+    #[test]
+    fn should_verify_place_with_every_constraint() {
+        let constraints = PlaceConstraints {
+            category_group_codes: vec!["FD6".to_string()],
+            category_name_patterns: vec!["음식점 > 양식*".to_string()],
+            place_ids: vec!["234127678".to_string(), "1".to_string()],
+            regions: vec!["부산".to_string(), "서울 강남구".to_string()],
+            enforcement: ConstraintEnforcement::Reject,
+            check_in_radius: None,
+            verified_visit_required: false,
+        };
+
+        assert!(constraints.verify(&place()).is_ok());
+        assert!(PlaceConstraints::default().verify(&place()).is_ok());
+    }
+
+    #[test]
+    fn should_err_with_reason_when_place_mismatches() {
+        let constraints = PlaceConstraints {
+            category_group_codes: vec!["CE7".to_string()],
+            ..PlaceConstraints::default()
+        };
+
+        match constraints.verify(&place()).unwrap_err() {
+            ApiError::PlaceMismatch(reason) => {
+                assert_eq!(reason, "place category_group_code must match one of: CE7")
+            }
+            e => panic!("unexpected error: {:?}", e),
+        }
+    }
+
+    #[test]
+    fn should_flag_or_reject_following_enforcement() {
+        let mut constraints = PlaceConstraints {
+            regions: vec!["부산".to_string()],
+            ..PlaceConstraints::default()
+        };
+
+        assert!(matches!(
+            constraints.check(&place()).unwrap_err(),
+            ApiError::PlaceMismatch(_)
+        ));
+
+        constraints.enforcement = ConstraintEnforcement::Flag;
+        assert!(constraints.check(&place()).unwrap());
+
+        constraints.regions = vec!["서울".to_string()];
+        assert!(!constraints.check(&place()).unwrap());
+    }
+
+    #[test]
+    fn should_verify_visit_within_radius() {
+        let near = GeoPoint {
+            latitude: 37.5270,
+            longitude: 127.0354,
+        };
+        let far = GeoPoint {
+            latitude: 37.5300,
+            longitude: 127.0354,
+        };
+
+        assert!(is_verified_visit(Some(&near), &place(), 100.0));
+        assert!(!is_verified_visit(Some(&far), &place(), 100.0));
+        assert!(is_verified_visit(Some(&far), &place(), 500.0));
+        assert!(!is_verified_visit(None, &place(), 100.0));
+    }
+
+    #[test]
+    fn should_err_with_invalid_check_in_radius() {
+        for radius in [-1.0, 0.0, f64::NAN, f64::INFINITY] {
+            let constraints = PlaceConstraints {
+                check_in_radius: Some(radius),
+                ..PlaceConstraints::default()
+            };
+            let errors = constraints.validate("stamps[0].constraints");
+
+            assert_eq!(errors.len(), 1);
+            assert_eq!(errors[0].field, "stamps[0].constraints.check_in_radius");
+        }
+
+        assert!(PlaceConstraints::default()
+            .validate("constraints")
+            .is_empty());
+    }
+
+    #[test]
+    fn should_read_constraints_with_defaults() {
+        let constraints =
+            serde_json::from_str::<PlaceConstraints>(r#"{"regions":["서울"]}"#).unwrap();
+
+        assert_eq!(constraints.enforcement, ConstraintEnforcement::Reject);
+        assert!(constraints.place_ids.is_empty());
+        assert_eq!(
+            constraints.check_in_radius(),
+            DEFAULT_CHECK_IN_RADIUS_METERS
+        );
+        assert!(!constraints.verified_visit_required);
+    }
+}
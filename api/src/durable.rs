@@ -1,6 +1,8 @@
 use async_trait::async_trait;
-use serde::Deserialize;
-use worker::{Error, Storage};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use worker::wasm_bindgen::JsValue;
+use worker::{Env, Error, Method, Request, RequestInit, Storage, Stub};
 
 use crate::api_result::ApiResult;
 use crate::ApiError;
@@ -27,3 +29,35 @@ impl DurableStorageFind for Storage {
         }
     }
 }
+
+pub fn get_durable_stub(env: &Env, name: &str) -> worker::Result<Stub> {
+    env.durable_object(name)?.id_from_name(name)?.get_stub()
+}
+
+/// Sends a JSON request to another durable object on a path that is never routed by the gateway.
+pub async fn fetch_internal<B: Serialize, R: DeserializeOwned>(
+    stub: &Stub,
+    method: Method,
+    path: &str,
+    body: Option<&B>,
+) -> ApiResult<R> {
+    let mut init = RequestInit::new();
+    init.with_method(method);
+
+    if let Some(body) = body {
+        let body = serde_json::to_string(body)
+            .map_err(|_| ApiError::ServerError("invalid internal request".to_string()))?;
+        init.with_body(Some(JsValue::from(body)));
+    }
+
+    let req = Request::new_with_init(&format!("https://internal{}", path), &init)?;
+    let mut res = stub.fetch_with_request(req).await?;
+
+    match res.status_code() {
+        200 => Ok(res.json::<R>().await?),
+        status => Err(ApiError::ServerError(format!(
+            "internal request failed with {}",
+            status
+        ))),
+    }
+}
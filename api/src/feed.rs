@@ -0,0 +1,66 @@
+use serde::{Deserialize, Serialize};
+
+use crate::foodnotes::Foodnote;
+use crate::pagination::Page;
+use crate::users::UserProfileDto;
+
+/// Seconds anyone may cache the feed for. Changes to it show up within this time.
+pub const FEED_CACHE_MAX_AGE: i32 = 30;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FeedItemDto {
+    #[serde(flatten)]
+    pub foodnote: Foodnote,
+    /// `None` when the author no longer exists.
+    pub author: Option<UserProfileDto>,
+}
+
+impl FeedItemDto {
+    pub fn page(page: Page<Foodnote>, profiles: &[UserProfileDto]) -> Page<Self> {
+        let items = page
+            .items
+            .into_iter()
+            .map(|foodnote| {
+                let author = profiles
+                    .iter()
+                    .find(|x| x.id == foodnote.author_id)
+                    .cloned();
+                Self { foodnote, author }
+            })
+            .collect();
+
+        Page {
+            items,
+            next_cursor: page.next_cursor,
+        }
+    }
+}
+
+#[cfg(test)]
+mod feed_tests {
+    use super::*;
+    use crate::test_fixtures::foodnote;
+
+    #[test]
+    fn should_attach_author_profiles() {
+        let mut unknown = foodnote("b", 20);
+        unknown.author_id = "bob".to_string();
+        let page = Page {
+            items: vec![foodnote("a", 10), unknown],
+            next_cursor: Some("cursor".to_string()),
+        };
+        let profiles = vec![UserProfileDto {
+            id: "alice".to_string(),
+            name: Some("Alice".to_string()),
+        }];
+
+        let page = FeedItemDto::page(page, &profiles);
+
+        assert_eq!(
+            page.items[0].author.as_ref().unwrap().name.as_deref(),
+            Some("Alice")
+        );
+        assert!(page.items[1].author.is_none());
+        assert_eq!(page.next_cursor.as_deref(), Some("cursor"));
+    }
+}
@@ -1,26 +1,141 @@
+use std::collections::{HashMap, HashSet};
+
 use chrono::Utc;
+use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
 use serde_json::json;
 use worker::*;
 
 use crate::{ApiError, uid};
 use crate::api_result::ApiResult;
+use crate::badges::{Badge, BadgeDto};
+use crate::challenges::{
+    challenges_with_stamps, find_active_stamp, Challenge, ChallengesClient, Stamp,
+};
+use crate::claim_codes::{get_jwt_for_claim_codes, verify_claim_code, ClaimStampDto, Redemption};
+use crate::constraints::{is_verified_visit, DEFAULT_CHECK_IN_RADIUS_METERS};
 use crate::durable::DurableStorageFind;
+use crate::feed::{FeedItemDto, FEED_CACHE_MAX_AGE};
+use crate::geo::GeoPoint;
+use crate::identity::get_identity;
+use crate::images::{
+    check_uploaded_images, delete_images, image_key_of_url, unreferenced_image_keys, upload_image,
+    ImageDto, R2Bucket,
+};
+use crate::leaderboards::{parse_leaderboard_limit, Leaderboard, RankEntry};
+use crate::pagination::{
+    decode_cursor, encode_cursor, parse_page_limit, KeyRange, Page, PageOrder,
+};
 use crate::place::PlaceDocument;
-use crate::req::ParseReqJson;
-use crate::res::response;
+use crate::progress::{collect_stamps, list_active_progress, ChallengeProgress, CollectedStamp};
+use crate::req::{path_segments, ParseReqJson, ReqQuery};
+use crate::res::{response, response_with_cache};
+use crate::users::UsersClient;
+use crate::visibility::{visibility_fields, Visibility};
 
 const ID_PREFIX: &str = "id_";
 const AUTHOR_ID_PREFIX: &str = "author_";
+const COLLECTED_PREFIX: &str = "collected_";
+const COLLECTION_SYNCED_PREFIX: &str = "collection_synced_";
+const BADGE_PREFIX: &str = "badge_";
+const RANK_PREFIX: &str = "rank_";
+const CHALLENGE_RANK_PREFIX: &str = "rank_challenge_";
+const GLOBAL_RANK_PREFIX: &str = "rank_global_";
+const RANKED_PREFIX: &str = "ranked_";
+const RANK_ORDER_SYNCED_KEY: &str = "synced_rank_order";
+const LEADERBOARD_OPT_OUT_PREFIX: &str = "leaderboard_opt_out_";
+const FEED_PREFIX: &str = "feed_";
+const FEED_SYNCED_KEY: &str = "synced_feed";
+const STAMP_REFERENCE_PREFIX: &str = "stamp_";
+const STAMP_REFERENCES_SYNCED_KEY: &str = "synced_stamp_references";
+/// Durable storage reads and writes at most this many keys at once.
+const MAX_KEYS_PER_OPERATION: usize = 128;
 
 pub fn foodnote_id_key(id: &str) -> String {
     format!("{}{}", ID_PREFIX, id)
 }
 
+/// Where every foodnote id of an author used to be kept in one value, before the author index.
 pub fn foodnote_author_id_key(author_id: &str) -> String {
     format!("{}{}", AUTHOR_ID_PREFIX, author_id)
 }
 
+pub fn author_index_prefix(author_id: &str) -> String {
+    format!("{}{}_", AUTHOR_ID_PREFIX, author_id)
+}
+
+/// One key per foodnote of an author, zero-padded so that listing returns the oldest first.
+pub fn author_index_key(author_id: &str, timestamp: i64, foodnote_id: &str) -> String {
+    format!(
+        "{}{:012}_{}",
+        author_index_prefix(author_id),
+        timestamp.max(0),
+        foodnote_id
+    )
+}
+
+/// One key per public foodnote, zero-padded so that listing returns the oldest first.
+pub fn feed_key(timestamp: i64, foodnote_id: &str) -> String {
+    format!("{}{:012}_{}", FEED_PREFIX, timestamp.max(0), foodnote_id)
+}
+
+/// Stamp ids may contain `_`, so a `/` ends the stamp id, or `a` would list the foodnotes of `a_b`.
+pub fn stamp_references_prefix(stamp_id: &str) -> String {
+    format!("{}{}/", STAMP_REFERENCE_PREFIX, stamp_id)
+}
+
+/// One key per foodnote claiming a stamp, with its author as the value.
+pub fn stamp_reference_key(stamp_id: &str, foodnote_id: &str) -> String {
+    format!("{}{}", stamp_references_prefix(stamp_id), foodnote_id)
+}
+
+pub fn collected_stamps_prefix(author_id: &str) -> String {
+    format!("{}{}_", COLLECTED_PREFIX, author_id)
+}
+
+pub fn collected_stamp_key(author_id: &str, stamp_id: &str) -> String {
+    format!("{}{}", collected_stamps_prefix(author_id), stamp_id)
+}
+
+pub fn collection_synced_key(author_id: &str) -> String {
+    format!("{}{}", COLLECTION_SYNCED_PREFIX, author_id)
+}
+
+pub fn badges_prefix(author_id: &str) -> String {
+    format!("{}{}_", BADGE_PREFIX, author_id)
+}
+
+pub fn badge_key(author_id: &str, challenge_id: &str) -> String {
+    format!("{}{}", badges_prefix(author_id), challenge_id)
+}
+
+pub fn challenge_ranks_prefix(challenge_id: &str) -> String {
+    format!("{}{}_", CHALLENGE_RANK_PREFIX, challenge_id)
+}
+
+/// The entry of a user in the leaderboard kept under `ranks_prefix`.
+pub fn rank_key(ranks_prefix: &str, user_id: &str) -> String {
+    format!("{}{}", ranks_prefix, user_id)
+}
+
+/// Where the entries of the leaderboard kept under `ranks_prefix` are also kept in rank order,
+/// e.g. `ranked_global_` for `rank_global_`.
+pub fn ranked_prefix(ranks_prefix: &str) -> String {
+    format!(
+        "{}{}",
+        RANKED_PREFIX,
+        ranks_prefix.trim_start_matches(RANK_PREFIX)
+    )
+}
+
+pub fn ranked_key(ranks_prefix: &str, entry: &RankEntry) -> String {
+    format!("{}{}", ranked_prefix(ranks_prefix), entry.sort_key())
+}
+
+pub fn leaderboard_opt_out_key(user_id: &str) -> String {
+    format!("{}{}", LEADERBOARD_OPT_OUT_PREFIX, user_id)
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Foodnote {
     pub id: String,
@@ -29,30 +144,137 @@ pub struct Foodnote {
     pub text: String,
     pub place: PlaceDocument,
     pub timestamp: i64,
+    /// When the author visited the place, which may be long before writing the foodnote.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub visited_at: Option<i64>,
     pub img_urls: Vec<String>,
-    pub is_public: bool,
+    #[serde(flatten, with = "visibility_fields")]
+    pub visibility: Visibility,
+    /// Lets anyone with it read the foodnote while it is link-only.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub share_token: Option<String>,
+    /// The place doesn't match the constraints of the stamp, which only flag it.
+    #[serde(default)]
+    pub place_mismatch: bool,
+    /// The author checked in near the place when writing the foodnote.
+    #[serde(default)]
+    pub verified_visit: bool,
+    /// The stamp only counted verified visits when the foodnote was written.
+    #[serde(default)]
+    pub verified_visit_required: bool,
 }
 
 impl Foodnote {
-    pub fn new(dto: CreateFoodnoteDto) -> Self {
+    pub fn new(author_id: &str, dto: CreateFoodnoteDto) -> Self {
         let id = uid!();
         let timestamp = Utc::now().timestamp();
 
-        Self {
+        let mut foodnote = Self {
             id,
             stamp_id: dto.stamp_id,
-            author_id: dto.author_id,
+            author_id: author_id.to_owned(),
             text: dto.text,
             place: dto.place,
             timestamp,
+            visited_at: dto.visited_at,
             img_urls: dto.img_urls,
-            is_public: dto.is_public,
+            visibility: Visibility::Private,
+            share_token: None,
+            place_mismatch: false,
+            verified_visit: false,
+            verified_visit_required: false,
+        };
+        foodnote.set_visibility(dto.visibility);
+
+        foodnote
+    }
+
+    pub fn update(&mut self, updates: &UpdateFoodnoteDto) -> &mut Self {
+        if let Some(stamp_id) = &updates.stamp_id {
+            self.stamp_id = stamp_id.to_owned();
+        }
+        if let Some(text) = &updates.text {
+            self.text = text.to_owned();
+        }
+        if let Some(visited_at) = updates.visited_at {
+            self.visited_at = Some(visited_at);
+        }
+        if let Some(img_urls) = &updates.img_urls {
+            self.img_urls = img_urls.clone();
+        }
+        if updates.visibility.is_some() || updates.is_public.is_some() {
+            self.set_visibility(Visibility::from_fields(
+                updates.visibility,
+                updates.is_public,
+            ));
+        }
+
+        self
+    }
+
+    /// A link-only foodnote gets an unguessable share token. Leaving link-only revokes it, so
+    /// links shared before stop working.
+    pub fn set_visibility(&mut self, visibility: Visibility) -> &mut Self {
+        self.visibility = visibility;
+        self.share_token = match visibility {
+            Visibility::LinkOnly => self.share_token.take().or_else(|| Some(uid!(32))),
+            _ => None,
+        };
+
+        self
+    }
+
+    pub fn is_public(&self) -> bool {
+        self.visibility.is_public()
+    }
+
+    /// Flags the foodnote against the constraints of its stamp, returning the check-in radius.
+    pub fn check_stamp(&mut self, stamp: &Stamp) -> ApiResult<f64> {
+        match &stamp.constraints {
+            Some(constraints) => {
+                self.place_mismatch = constraints.check(&self.place)?;
+                self.verified_visit_required = constraints.verified_visit_required;
+                Ok(constraints.check_in_radius())
+            }
+            None => {
+                self.place_mismatch = false;
+                self.verified_visit_required = false;
+                Ok(DEFAULT_CHECK_IN_RADIUS_METERS)
+            }
+        }
+    }
+
+    pub fn is_author(&self, user_id: &str) -> bool {
+        self.author_id == user_id
+    }
+
+    pub fn is_visible_to(&self, user_id: &str) -> bool {
+        self.is_public() || self.is_author(user_id)
+    }
+
+    pub fn is_shared_with(&self, share_token: &str) -> bool {
+        match (&self.visibility, &self.share_token) {
+            (Visibility::Public, _) => true,
+            (Visibility::LinkOnly, Some(x)) => x == share_token,
+            _ => false,
         }
     }
 
+    pub fn counts_toward_progress(&self) -> bool {
+        !self.place_mismatch && (self.verified_visit || !self.verified_visit_required)
+    }
+
     pub fn id_key(&self) -> String {
         foodnote_id_key(&self.id)
     }
+
+    pub fn feed_key(&self) -> String {
+        feed_key(self.timestamp, &self.id)
+    }
+
+    pub fn stamp_reference_key(&self) -> String {
+        stamp_reference_key(&self.stamp_id, &self.id)
+    }
 }
 
 #[durable_object]
@@ -78,83 +300,1276 @@ impl Foodnotes {
         }
     }
 
+    /// Ids of the foodnotes of an author, the oldest first.
     pub async fn list_ids_for_author(&self, author_id: &str) -> ApiResult<Vec<String>> {
-        let ids = self
-            .state
-            .storage()
-            .find::<Vec<String>>(&foodnote_author_id_key(author_id))
-            .await?
-            .unwrap_or(Vec::new());
+        self.migrate_author_index(author_id).await?;
+
+        let prefix = author_index_prefix(author_id);
+        let options = ListOptions::new().prefix(&prefix);
+        let entries = self.state.storage().list_with_options(options).await?;
+
+        let mut ids = Vec::<String>::new();
+        entries.for_each(&mut |value, _| {
+            ids.push(value.into_serde::<String>().unwrap());
+        });
 
         Ok(ids)
     }
 
     pub async fn list_for_author(&self, author_id: &str) -> ApiResult<Vec<Foodnote>> {
-        let storage = self.state.storage();
-        let ids = self
-            .list_ids_for_author(&author_id)
-            .await?
-            .into_iter()
-            .map(|id| foodnote_id_key(&id))
-            .collect();
+        let ids = self.list_ids_for_author(author_id).await?;
+
+        self.get_multiple(&ids).await
+    }
+
+    /// A page of the foodnotes of an author matching `filter`, continuing after `cursor`.
+    pub async fn list_page_for_author(
+        &self,
+        author_id: &str,
+        filter: &FoodnoteFilter,
+        order: PageOrder,
+        limit: usize,
+        cursor: Option<&str>,
+    ) -> ApiResult<Page<Foodnote>> {
+        self.migrate_author_index(author_id).await?;
+
+        let prefix = author_index_prefix(author_id);
+        let range = filter.key_range(author_id);
+
+        self.list_page(&prefix, range, filter, order, limit, cursor)
+            .await
+    }
+
+    /// A page of the recent public foodnotes of every author, the latest first.
+    pub async fn list_feed(&self, limit: usize, cursor: Option<&str>) -> ApiResult<Page<Foodnote>> {
+        self.sync_feed().await?;
+
+        let filter = FoodnoteFilter {
+            visibility: Some(Visibility::Public),
+            ..Default::default()
+        };
+
+        self.list_page(
+            FEED_PREFIX,
+            KeyRange::default(),
+            &filter,
+            PageOrder::Newest,
+            limit,
+            cursor,
+        )
+        .await
+    }
+
+    /// A page of the foodnotes of an index with keys under `prefix`, continuing after `cursor`.
+    async fn list_page(
+        &self,
+        prefix: &str,
+        mut range: KeyRange,
+        filter: &FoodnoteFilter,
+        order: PageOrder,
+        limit: usize,
+        cursor: Option<&str>,
+    ) -> ApiResult<Page<Foodnote>> {
+        if let Some(cursor) = cursor {
+            range = range.after(&decode_cursor(prefix, cursor)?, order);
+        }
+
+        // note: one more than the limit tells whether there is a next page.
+        let mut matched = Vec::<(String, Foodnote)>::new();
+        while matched.len() <= limit {
+            let entries = self
+                .list_index(prefix, &range, order, MAX_KEYS_PER_OPERATION)
+                .await?;
+            let ids = entries
+                .iter()
+                .map(|(_, id)| id.to_owned())
+                .collect::<Vec<String>>();
+            let mut found = self
+                .get_multiple(&ids)
+                .await?
+                .into_iter()
+                .map(|x| (x.id.to_owned(), x))
+                .collect::<HashMap<String, Foodnote>>();
+
+            for (key, id) in &entries {
+                match found.remove(id) {
+                    Some(foodnote) if filter.matches(&foodnote) => {
+                        matched.push((key.to_owned(), foodnote))
+                    }
+                    _ => continue,
+                }
+                if matched.len() > limit {
+                    break;
+                }
+            }
+
+            match entries.last() {
+                Some((key, _)) if entries.len() == MAX_KEYS_PER_OPERATION => {
+                    range = range.after(key, order);
+                }
+                _ => break,
+            }
+        }
+
+        let next_cursor = match matched.len() > limit {
+            true => {
+                matched.truncate(limit);
+                matched.last().map(|(key, _)| encode_cursor(prefix, key))
+            }
+            false => None,
+        };
+        let items = matched.into_iter().map(|(_, x)| x).collect();
 
-        let mut foodnotes = Vec::<Foodnote>::new();
-        storage.get_multiple(ids).await?.for_each(&mut |value, _| {
-            let val = value.into_serde::<Foodnote>().unwrap();
-            foodnotes.push(val);
+        Ok(Page { items, next_cursor })
+    }
+
+    /// Entries of an index within `range` as keys and foodnote ids.
+    async fn list_index(
+        &self,
+        prefix: &str,
+        range: &KeyRange,
+        order: PageOrder,
+        limit: usize,
+    ) -> ApiResult<Vec<(String, String)>> {
+        let mut options = ListOptions::new()
+            .prefix(prefix)
+            .reverse(order == PageOrder::Newest)
+            .limit(limit);
+        if let Some(start) = &range.start {
+            options = options.start(start);
+        }
+        if let Some(end) = &range.end {
+            options = options.end(end);
+        }
+        let entries = self.state.storage().list_with_options(options).await?;
+
+        let mut index = Vec::<(String, String)>::new();
+        entries.for_each(&mut |value, key| {
+            let id = value.into_serde::<String>().unwrap();
+            index.push((key.as_string().unwrap(), id));
         });
 
+        Ok(index)
+    }
+
+    /// Foodnotes of `ids` in the same order, leaving out those that don't exist.
+    pub async fn get_multiple(&self, ids: &[String]) -> ApiResult<Vec<Foodnote>> {
+        let storage = self.state.storage();
+        let mut found = HashMap::<String, Foodnote>::new();
+
+        for chunk in ids.chunks(MAX_KEYS_PER_OPERATION) {
+            let keys = chunk.iter().map(|id| foodnote_id_key(id)).collect();
+            storage.get_multiple(keys).await?.for_each(&mut |value, _| {
+                let foodnote = value.into_serde::<Foodnote>().unwrap();
+                found.insert(foodnote.id.to_owned(), foodnote);
+            });
+        }
+
+        let foodnotes = ids.iter().filter_map(|id| found.remove(id)).collect();
+
         Ok(foodnotes)
     }
 
-    pub async fn create(&self, foodnote: Foodnote) -> ApiResult<Foodnote> {
+    /// Stores a new foodnote, then awards and ranks the author in the challenges of its stamp.
+    /// `challenges` are every challenge, fetched once by the caller.
+    pub async fn create(
+        &self,
+        foodnote: Foodnote,
+        challenges: &[Challenge],
+    ) -> ApiResult<Foodnote> {
         self.state
             .storage()
             .put(&foodnote.id_key(), &foodnote)
             .await?;
         self.append_as_author(&foodnote).await?;
+        self.index_in_feed(&foodnote, None).await?;
+        self.index_stamp_reference(&foodnote, None).await?;
+        self.collect_stamp(&foodnote).await?;
+        let affected = challenges_with_stamps(challenges, &[&foodnote.stamp_id]);
+        self.award_badges(&foodnote.author_id, &affected).await?;
+        self.update_ranks(&foodnote.author_id, &affected).await?;
 
         Ok(foodnote)
     }
 
+    /// Stores the changes of a foodnote. When it claims another stamp, the collected stamps,
+    /// badges and ranks of the author follow, in the challenges of both stamps among `challenges`.
+    pub async fn update(
+        &self,
+        foodnote: &Foodnote,
+        previous: &Foodnote,
+        challenges: &[Challenge],
+    ) -> ApiResult<()> {
+        self.state
+            .storage()
+            .put(&foodnote.id_key(), &foodnote)
+            .await?;
+        self.index_in_feed(foodnote, Some(previous)).await?;
+        self.index_stamp_reference(foodnote, Some(previous)).await?;
+
+        if foodnote.stamp_id != previous.stamp_id {
+            self.reset_collected_stamps(&foodnote.author_id).await?;
+            let awarding = challenges_with_stamps(challenges, &[&foodnote.stamp_id]);
+            self.award_badges(&foodnote.author_id, &awarding).await?;
+            let affected =
+                challenges_with_stamps(challenges, &[&foodnote.stamp_id, &previous.stamp_id]);
+            self.update_ranks(&foodnote.author_id, &affected).await?;
+        }
+        if foodnote.img_urls != previous.img_urls {
+            self.collect_images(&foodnote.author_id, &previous.img_urls)
+                .await?;
+        }
+
+        Ok(())
+    }
+
+    /// Deletes a foodnote. Badges already awarded are kept, like when stamps are added to a
+    /// completed challenge. Ranks follow in the challenges of its stamp among `challenges`.
+    pub async fn delete(&self, foodnote: &Foodnote, challenges: &[Challenge]) -> ApiResult<()> {
+        let mut storage = self.state.storage();
+        storage.delete(&foodnote.id_key()).await?;
+        storage.delete(&foodnote.feed_key()).await?;
+        storage.delete(&foodnote.stamp_reference_key()).await?;
+        self.remove_as_author(foodnote).await?;
+        self.reset_collected_stamps(&foodnote.author_id).await?;
+        let affected = challenges_with_stamps(challenges, &[&foodnote.stamp_id]);
+        self.update_ranks(&foodnote.author_id, &affected).await?;
+        self.collect_images(&foodnote.author_id, &foodnote.img_urls)
+            .await?;
+
+        Ok(())
+    }
+
+    /// Deletes uploaded images among `img_urls` no foodnote of the author refers to anymore.
+    async fn collect_images(&self, author_id: &str, img_urls: &[String]) -> ApiResult<()> {
+        if img_urls.iter().all(|x| image_key_of_url(x).is_none()) {
+            return Ok(());
+        }
+
+        let kept = self
+            .list_for_author(author_id)
+            .await?
+            .into_iter()
+            .flat_map(|x| x.img_urls)
+            .collect::<Vec<String>>();
+        let keys = unreferenced_image_keys(img_urls, &kept);
+        if keys.is_empty() {
+            return Ok(());
+        }
+
+        delete_images(&R2Bucket::new(&self.env)?, &keys).await
+    }
+
+    pub async fn is_leaderboard_opted_out(&self, user_id: &str) -> ApiResult<bool> {
+        let opted_out = self
+            .state
+            .storage()
+            .find::<bool>(&leaderboard_opt_out_key(user_id))
+            .await?;
+
+        Ok(opted_out.unwrap_or(false))
+    }
+
+    /// Opting out removes the user from every leaderboard; opting back in ranks them again.
+    pub async fn set_leaderboard_opt_out(&self, user_id: &str, opt_out: bool) -> ApiResult<()> {
+        let mut storage = self.state.storage();
+        let key = leaderboard_opt_out_key(user_id);
+        let challenges = ChallengesClient::new(&self.env)?.list_all().await?;
+
+        if !opt_out {
+            storage.delete(&key).await?;
+            return self.update_ranks(user_id, &challenges).await;
+        }
+
+        storage.put(&key, true).await?;
+
+        for challenge in &challenges {
+            self.set_rank(&challenge_ranks_prefix(&challenge.id), user_id, None)
+                .await?;
+        }
+        self.set_rank(GLOBAL_RANK_PREFIX, user_id, None).await
+    }
+
+    /// Recomputes the leaderboard entries of an author in `challenges` and the global leaderboard
+    /// from their collected stamps and badges.
+    pub async fn update_ranks(&self, author_id: &str, challenges: &[Challenge]) -> ApiResult<()> {
+        if self.is_leaderboard_opted_out(author_id).await? {
+            return Ok(());
+        }
+
+        let collected = self.list_collected_stamps(author_id).await?;
+        let badges = self.list_badges(author_id).await?;
+
+        for challenge in challenges {
+            let progress = ChallengeProgress::new(challenge, &collected);
+            let badge = badges.iter().find(|x| x.challenge_id == challenge.id);
+            let entry = RankEntry::for_challenge(author_id, &progress, badge);
+
+            self.set_rank(&challenge_ranks_prefix(&challenge.id), author_id, entry)
+                .await?;
+        }
+
+        let entry = RankEntry::global(author_id, &collected, &badges);
+        self.set_rank(GLOBAL_RANK_PREFIX, author_id, entry).await
+    }
+
+    /// Keeps the entry of a user in a leaderboard both by user and in rank order.
+    async fn set_rank(
+        &self,
+        ranks_prefix: &str,
+        user_id: &str,
+        entry: Option<RankEntry>,
+    ) -> ApiResult<()> {
+        let mut storage = self.state.storage();
+        let key = rank_key(ranks_prefix, user_id);
+
+        let previous = storage.find::<RankEntry>(&key).await?;
+        if previous == entry {
+            return Ok(());
+        }
+        if let Some(previous) = &previous {
+            storage.delete(&ranked_key(ranks_prefix, previous)).await?;
+        }
+
+        match &entry {
+            Some(entry) => {
+                storage.put(&ranked_key(ranks_prefix, entry), entry).await?;
+                storage.put(&key, entry).await?;
+            }
+            None => {
+                storage.delete(&key).await?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// The first `limit` entries of a leaderboard, in rank order.
+    async fn list_top_ranks(&self, ranks_prefix: &str, limit: usize) -> ApiResult<Vec<RankEntry>> {
+        let prefix = ranked_prefix(ranks_prefix);
+        let options = ListOptions::new().prefix(&prefix).limit(limit);
+        let entries = self.state.storage().list_with_options(options).await?;
+
+        let mut ranks = Vec::<RankEntry>::new();
+        entries.for_each(&mut |value, _| {
+            ranks.push(value.into_serde::<RankEntry>().unwrap());
+        });
+
+        Ok(ranks)
+    }
+
+    /// The entry of a user in a leaderboard with its rank, counting the entries ahead of it a page
+    /// at a time.
+    async fn find_rank(
+        &self,
+        ranks_prefix: &str,
+        user_id: &str,
+    ) -> ApiResult<Option<(u32, RankEntry)>> {
+        let storage = self.state.storage();
+        let entry = match storage
+            .find::<RankEntry>(&rank_key(ranks_prefix, user_id))
+            .await?
+        {
+            Some(x) => x,
+            None => return Ok(None),
+        };
+
+        let prefix = ranked_prefix(ranks_prefix);
+        let end = ranked_key(ranks_prefix, &entry);
+        let mut ahead = 0;
+        let mut cursor = None::<String>;
+        loop {
+            let start = cursor.as_ref().map(|x| format!("{}\u{0}", x));
+            let mut options = ListOptions::new()
+                .prefix(&prefix)
+                .end(&end)
+                .limit(MAX_KEYS_PER_OPERATION);
+            if let Some(start) = &start {
+                options = options.start(start);
+            }
+            let entries = storage.list_with_options(options).await?;
+
+            let mut count = 0;
+            entries.for_each(&mut |_, key| {
+                count += 1;
+                cursor = key.as_string();
+            });
+            ahead += count;
+            if count < MAX_KEYS_PER_OPERATION {
+                break;
+            }
+        }
+
+        Ok(Some((ahead as u32 + 1, entry)))
+    }
+
+    pub async fn get_leaderboard(
+        &self,
+        prefix: &str,
+        user_id: &str,
+        limit: usize,
+    ) -> ApiResult<Leaderboard> {
+        self.sync_rank_order().await?;
+
+        let top = self.list_top_ranks(prefix, limit).await?;
+        let me = match top.iter().position(|x| x.user_id == user_id) {
+            Some(index) => Some((index as u32 + 1, top[index].clone())),
+            None => self.find_rank(prefix, user_id).await?,
+        };
+        let opted_out = self.is_leaderboard_opted_out(user_id).await?;
+        let leaderboard = Leaderboard::new(top, me, opted_out);
+
+        let ids = leaderboard
+            .top
+            .iter()
+            .chain(leaderboard.me.iter())
+            .map(|x| x.entry.user_id.to_owned())
+            .collect();
+        let profiles = UsersClient::new(&self.env)?.list_profiles(ids).await?;
+
+        Ok(leaderboard.with_profiles(&profiles))
+    }
+
+    pub async fn list_badges(&self, author_id: &str) -> ApiResult<Vec<Badge>> {
+        let prefix = badges_prefix(author_id);
+        let options = ListOptions::new().prefix(&prefix);
+        let entries = self.state.storage().list_with_options(options).await?;
+
+        let mut badges = Vec::<Badge>::new();
+        entries.for_each(&mut |value, _| {
+            badges.push(value.into_serde::<Badge>().unwrap());
+        });
+
+        Ok(badges)
+    }
+
+    /// Awards a badge for every active challenge among `challenges` the author has completed.
+    /// Challenges that already have a badge are left as they are, so awarding is idempotent.
+    /// Returns the challenges the author is awarded.
+    pub async fn award_badges(
+        &self,
+        author_id: &str,
+        challenges: &[Challenge],
+    ) -> ApiResult<Vec<Challenge>> {
+        let now = Utc::now().timestamp();
+        let challenges = challenges
+            .iter()
+            .filter(|x| x.is_active_at(now))
+            .collect::<Vec<&Challenge>>();
+        if challenges.is_empty() {
+            return Ok(vec![]);
+        }
+
+        let collected = self.list_collected_stamps(author_id).await?;
+        let mut storage = self.state.storage();
+        let mut awarded = Vec::<Challenge>::new();
+
+        for challenge in challenges {
+            let key = badge_key(author_id, &challenge.id);
+            if storage.find::<Badge>(&key).await?.is_some() {
+                continue;
+            }
+
+            let progress = ChallengeProgress::new(challenge, &collected);
+            if let Some(badge) = Badge::for_progress(&progress, now) {
+                storage.put(&key, &badge).await?;
+                awarded.push(challenge.to_owned());
+            }
+        }
+
+        Ok(awarded)
+    }
+
+    pub async fn list_collected_stamps(&self, author_id: &str) -> ApiResult<Vec<CollectedStamp>> {
+        self.sync_collected_stamps(author_id).await?;
+
+        let prefix = collected_stamps_prefix(author_id);
+        let options = ListOptions::new().prefix(&prefix);
+        let entries = self.state.storage().list_with_options(options).await?;
+
+        let mut collected = Vec::<CollectedStamp>::new();
+        entries.for_each(&mut |value, _| {
+            collected.push(value.into_serde::<CollectedStamp>().unwrap());
+        });
+
+        Ok(collected)
+    }
+
+    /// Builds the collected stamps of an author from their foodnotes, once. Afterwards the
+    /// collection is kept up to date as foodnotes are created.
+    async fn sync_collected_stamps(&self, author_id: &str) -> ApiResult<bool> {
+        let mut storage = self.state.storage();
+        let synced_key = collection_synced_key(author_id);
+
+        if storage.find::<bool>(&synced_key).await?.is_some() {
+            return Ok(false);
+        }
+
+        let foodnotes = self.list_for_author(author_id).await?;
+        for collected in collect_stamps(&foodnotes) {
+            let key = collected_stamp_key(author_id, &collected.stamp_id);
+            storage.put(&key, &collected).await?;
+        }
+        storage.put(&synced_key, true).await?;
+
+        Ok(true)
+    }
+
+    /// Rebuilds the collected stamps of an author, for when a foodnote no longer claims a stamp.
+    async fn reset_collected_stamps(&self, author_id: &str) -> ApiResult<()> {
+        let mut storage = self.state.storage();
+        let prefix = collected_stamps_prefix(author_id);
+        let options = ListOptions::new().prefix(&prefix);
+        let entries = storage.list_with_options(options).await?;
+
+        let mut keys = Vec::<String>::new();
+        entries.for_each(&mut |_, key| {
+            keys.push(key.as_string().unwrap());
+        });
+        keys.push(collection_synced_key(author_id));
+        storage.delete_multiple(keys).await?;
+
+        self.sync_collected_stamps(author_id).await?;
+
+        Ok(())
+    }
+
+    async fn collect_stamp(&self, foodnote: &Foodnote) -> ApiResult<()> {
+        let author_id = &foodnote.author_id;
+
+        if !foodnote.counts_toward_progress() {
+            return Ok(());
+        }
+
+        // note: a fresh sync already includes the stored foodnote.
+        if self.sync_collected_stamps(author_id).await? {
+            return Ok(());
+        }
+
+        let key = collected_stamp_key(author_id, &foodnote.stamp_id);
+        let mut storage = self.state.storage();
+        let collected = match storage.find::<CollectedStamp>(&key).await? {
+            Some(mut x) => {
+                x.add(foodnote);
+                x
+            }
+            None => CollectedStamp::new(foodnote),
+        };
+
+        storage.put(&key, &collected).await?;
+
+        Ok(())
+    }
+
+    /// The stamps among `stamp_ids` that any foodnote claims.
+    pub async fn list_referenced_stamps(&self, stamp_ids: &[String]) -> ApiResult<Vec<String>> {
+        self.sync_stamp_references().await?;
+
+        let storage = self.state.storage();
+        let mut referenced = Vec::<String>::new();
+        for stamp_id in stamp_ids {
+            let prefix = stamp_references_prefix(stamp_id);
+            let options = ListOptions::new().prefix(&prefix).limit(1);
+            if storage.list_with_options(options).await?.size() > 0 {
+                referenced.push(stamp_id.to_owned());
+            }
+        }
+
+        Ok(referenced)
+    }
+
+    /// Awards badges and updates ranks in `challenges` for every author of a foodnote claiming one
+    /// of their stamps. Returns how many authors were refreshed.
+    pub async fn refresh_challenges(&self, challenges: &[Challenge]) -> ApiResult<usize> {
+        self.sync_stamp_references().await?;
+
+        let mut by_author = HashMap::<String, Vec<Challenge>>::new();
+        for challenge in challenges {
+            for author_id in self.list_stamp_authors(&challenge.stamp_ids()).await? {
+                by_author
+                    .entry(author_id)
+                    .or_default()
+                    .push(challenge.to_owned());
+            }
+        }
+
+        for (author_id, challenges) in &by_author {
+            self.award_badges(author_id, challenges).await?;
+            self.update_ranks(author_id, challenges).await?;
+        }
+
+        Ok(by_author.len())
+    }
+
+    /// Authors of the foodnotes claiming any of `stamp_ids`.
+    async fn list_stamp_authors(&self, stamp_ids: &[String]) -> ApiResult<HashSet<String>> {
+        let storage = self.state.storage();
+        let mut authors = HashSet::<String>::new();
+
+        for stamp_id in stamp_ids {
+            let prefix = stamp_references_prefix(stamp_id);
+            let mut cursor = None::<String>;
+            loop {
+                let start = cursor.as_ref().map(|x| format!("{}\u{0}", x));
+                let mut options = ListOptions::new()
+                    .prefix(&prefix)
+                    .limit(MAX_KEYS_PER_OPERATION);
+                if let Some(start) = &start {
+                    options = options.start(start);
+                }
+                let entries = storage.list_with_options(options).await?;
+
+                let mut count = 0;
+                entries.for_each(&mut |value, key| {
+                    count += 1;
+                    cursor = key.as_string();
+                    authors.insert(value.into_serde::<String>().unwrap());
+                });
+                if count < MAX_KEYS_PER_OPERATION {
+                    break;
+                }
+            }
+        }
+
+        Ok(authors)
+    }
+
     async fn append_as_author(&self, foodnote: &Foodnote) -> ApiResult<()> {
-        let id = &foodnote.id;
         let author_id = &foodnote.author_id;
+        self.migrate_author_index(author_id).await?;
 
-        let mut ids = self.list_ids_for_author(author_id).await?;
-        ids.push(id.to_owned());
-        console_log!("ids: {:?}", &ids);
+        let key = author_index_key(author_id, foodnote.timestamp, &foodnote.id);
+        self.state.storage().put(&key, &foodnote.id).await?;
 
-        self.state
-            .storage()
-            .put(&foodnote_author_id_key(author_id), &ids)
+        Ok(())
+    }
+
+    async fn remove_as_author(&self, foodnote: &Foodnote) -> ApiResult<()> {
+        let author_id = &foodnote.author_id;
+        self.migrate_author_index(author_id).await?;
+
+        let key = author_index_key(author_id, foodnote.timestamp, &foodnote.id);
+        self.state.storage().delete(&key).await?;
+
+        Ok(())
+    }
+
+    /// Keeps the foodnote in the feed exactly while it is public.
+    async fn index_in_feed(
+        &self,
+        foodnote: &Foodnote,
+        previous: Option<&Foodnote>,
+    ) -> ApiResult<()> {
+        let was_public = matches!(previous, Some(x) if x.is_public());
+        let mut storage = self.state.storage();
+
+        match (was_public, foodnote.is_public()) {
+            (false, true) => storage.put(&foodnote.feed_key(), &foodnote.id).await?,
+            (true, false) => {
+                storage.delete(&foodnote.feed_key()).await?;
+            }
+            _ => (),
+        }
+
+        Ok(())
+    }
+
+    /// Keeps the stamp a foodnote claims referenced, for as long as it claims it.
+    async fn index_stamp_reference(
+        &self,
+        foodnote: &Foodnote,
+        previous: Option<&Foodnote>,
+    ) -> ApiResult<()> {
+        let mut storage = self.state.storage();
+
+        match previous {
+            Some(x) if x.stamp_id == foodnote.stamp_id => return Ok(()),
+            Some(x) => {
+                storage.delete(&x.stamp_reference_key()).await?;
+            }
+            None => (),
+        }
+        storage
+            .put(&foodnote.stamp_reference_key(), &foodnote.author_id)
             .await?;
 
         Ok(())
     }
+
+    /// Adds the foodnotes made public before the feed existed to it, once.
+    async fn sync_feed(&self) -> ApiResult<()> {
+        self.sync_index(FEED_SYNCED_KEY, ID_PREFIX, |_, x: &Foodnote| {
+            match x.is_public() {
+                true => Some((x.feed_key(), x.id.to_owned())),
+                false => None,
+            }
+        })
+        .await
+    }
+
+    /// References the stamps of the foodnotes written before they were indexed, once.
+    async fn sync_stamp_references(&self) -> ApiResult<()> {
+        self.sync_index(STAMP_REFERENCES_SYNCED_KEY, ID_PREFIX, |_, x: &Foodnote| {
+            Some((x.stamp_reference_key(), x.author_id.to_owned()))
+        })
+        .await
+    }
+
+    /// Puts the leaderboard entries stored before they were kept in rank order in it, once.
+    async fn sync_rank_order(&self) -> ApiResult<()> {
+        self.sync_index(RANK_ORDER_SYNCED_KEY, RANK_PREFIX, |key, x: &RankEntry| {
+            let ranks_prefix = key.strip_suffix(&x.user_id)?;
+            Some((ranked_key(ranks_prefix, x), x.clone()))
+        })
+        .await
+    }
+
+    /// Writes the entries `index` makes of every value stored under `prefix`, a page at a time so
+    /// that no listing is unbounded. Where it got to is kept, so an interrupted sync goes on from
+    /// there.
+    async fn sync_index<T: DeserializeOwned, V: Serialize>(
+        &self,
+        synced_key: &str,
+        prefix: &str,
+        index: fn(&str, &T) -> Option<(String, V)>,
+    ) -> ApiResult<()> {
+        let mut storage = self.state.storage();
+        if storage.find::<bool>(synced_key).await?.is_some() {
+            return Ok(());
+        }
+
+        let cursor_key = format!("{}_cursor", synced_key);
+        let mut cursor = storage.find::<String>(&cursor_key).await?;
+        loop {
+            // note: the smallest key greater than the last one synced.
+            let start = cursor.as_ref().map(|x| format!("{}\u{0}", x));
+            let mut options = ListOptions::new()
+                .prefix(prefix)
+                .limit(MAX_KEYS_PER_OPERATION);
+            if let Some(start) = &start {
+                options = options.start(start);
+            }
+            let entries = storage.list_with_options(options).await?;
+
+            let mut count = 0;
+            let mut values = HashMap::<String, V>::new();
+            entries.for_each(&mut |value, key| {
+                count += 1;
+                cursor = key.as_string();
+                let key = key.as_string().unwrap();
+                if let Some((key, value)) = index(&key, &value.into_serde::<T>().unwrap()) {
+                    values.insert(key, value);
+                }
+            });
+            if !values.is_empty() {
+                storage.put_multiple(values).await?;
+            }
+
+            if count < MAX_KEYS_PER_OPERATION {
+                break;
+            }
+            if let Some(cursor) = &cursor {
+                storage.put(&cursor_key, cursor).await?;
+            }
+        }
+        storage.put(synced_key, true).await?;
+        storage.delete(&cursor_key).await?;
+
+        Ok(())
+    }
+
+    /// Moves the ids of an author kept in one value to the author index, once. The value is only
+    /// deleted after every entry is written, so an interrupted migration is picked up again.
+    async fn migrate_author_index(&self, author_id: &str) -> ApiResult<()> {
+        let mut storage = self.state.storage();
+        let legacy_key = foodnote_author_id_key(author_id);
+
+        let ids = match storage.find::<Vec<String>>(&legacy_key).await? {
+            Some(ids) => ids,
+            None => return Ok(()),
+        };
+
+        let entries = self
+            .get_multiple(&ids)
+            .await?
+            .iter()
+            .map(|x| {
+                let key = author_index_key(author_id, x.timestamp, &x.id);
+                (key, x.id.to_owned())
+            })
+            .collect::<Vec<(String, String)>>();
+
+        for chunk in entries.chunks(MAX_KEYS_PER_OPERATION) {
+            let values = chunk.iter().cloned().collect::<HashMap<String, String>>();
+            storage.put_multiple(values).await?;
+        }
+        storage.delete(&legacy_key).await?;
+
+        Ok(())
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct CreateFoodnoteDto {
     pub stamp_id: String,
-    pub author_id: String,
+    /// Only accepted for older clients. The author is always the authenticated user.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub author_id: Option<String>,
     pub text: String,
     pub place: PlaceDocument,
+    /// Clients may prefill it with the `captured_at` of an uploaded image.
+    #[serde(default)]
+    pub visited_at: Option<i64>,
     pub img_urls: Vec<String>,
-    pub is_public: bool,
+    #[serde(flatten, with = "visibility_fields")]
+    pub visibility: Visibility,
+    /// Where the author is when writing the foodnote. Only used to verify the visit, never stored.
+    pub check_in: Option<GeoPoint>,
+}
+
+impl CreateFoodnoteDto {
+    /// Rejects a foodnote written in the name of anyone but `user_id`.
+    pub fn check_author(&self, user_id: &str) -> ApiResult<()> {
+        match &self.author_id {
+            Some(x) if x != user_id => Err(ApiError::NotFoodnoteAuthor),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Narrows the foodnotes of an author down. `from` and `to` are inclusive timestamps.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct FoodnoteFilter {
+    pub stamp_id: Option<String>,
+    pub from: Option<i64>,
+    pub to: Option<i64>,
+    pub visibility: Option<Visibility>,
+}
+
+impl FoodnoteFilter {
+    pub fn from_req(req: &Request) -> ApiResult<Self> {
+        let timestamp = |key: &str| -> ApiResult<Option<i64>> {
+            match req.query_param(key)? {
+                Some(x) => match x.parse::<i64>() {
+                    Ok(x) => Ok(Some(x)),
+                    Err(_) => Err(ApiError::BadRequest(format!("invalid {}", key))),
+                },
+                None => Ok(None),
+            }
+        };
+        let visibility = match req.query_param("visibility")? {
+            Some(x) => match Visibility::parse(&x) {
+                Some(x) => Some(x),
+                None => return Err(ApiError::BadRequest("invalid visibility".to_string())),
+            },
+            None => None,
+        };
+
+        Ok(Self {
+            stamp_id: req.query_param("stamp_id")?,
+            from: timestamp("from")?,
+            to: timestamp("to")?,
+            visibility,
+        })
+    }
+
+    pub fn matches(&self, foodnote: &Foodnote) -> bool {
+        let is_stamp = match &self.stamp_id {
+            Some(stamp_id) => stamp_id == &foodnote.stamp_id,
+            None => true,
+        };
+        let is_visibility = match self.visibility {
+            Some(visibility) => visibility == foodnote.visibility,
+            None => true,
+        };
+        let is_in_range = !matches!(self.from, Some(x) if foodnote.timestamp < x)
+            && !matches!(self.to, Some(x) if foodnote.timestamp > x);
+
+        is_stamp && is_visibility && is_in_range
+    }
+
+    /// Keys of the author index written between `from` and `to`.
+    pub fn key_range(&self, author_id: &str) -> KeyRange {
+        let prefix = author_index_prefix(author_id);
+
+        KeyRange {
+            start: self.from.map(|x| format!("{}{:012}", prefix, x.max(0))),
+            end: self.to.map(|x| format!("{}{:012}", prefix, (x + 1).max(0))),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct UpdateFoodnoteDto {
+    pub stamp_id: Option<String>,
+    pub text: Option<String>,
+    pub visited_at: Option<i64>,
+    pub img_urls: Option<Vec<String>>,
+    pub visibility: Option<Visibility>,
+    /// Only for older clients, `visibility` wins over it.
+    pub is_public: Option<bool>,
 }
 
-pub async fn list_my_foodnotes(foodnotes: &Foodnotes, req: Request) -> ApiResult<Vec<Foodnote>> {
-    let author_id = req.headers().get("X-Foodrhapsody-User")?.unwrap();
+pub async fn list_my_foodnotes(foodnotes: &Foodnotes, req: Request) -> ApiResult<Page<Foodnote>> {
+    let author_id = get_identity(&foodnotes.env, &req)?.user_id;
+    let filter = FoodnoteFilter::from_req(&req)?;
+    let order = PageOrder::parse(req.query_param("order")?.as_deref())?;
+    let limit = parse_page_limit(req.query_param("limit")?.as_deref());
+    let cursor = req.query_param("cursor")?;
 
-    foodnotes.list_for_author(&author_id).await
+    foodnotes
+        .list_page_for_author(&author_id, &filter, order, limit, cursor.as_deref())
+        .await
 }
 
 pub async fn add_my_foodnote(foodnotes: &Foodnotes, mut req: Request) -> ApiResult<Foodnote> {
+    let author_id = get_identity(&foodnotes.env, &req)?.user_id;
     let dto = req.parse_json::<CreateFoodnoteDto>().await?;
-    let foodnote = Foodnote::new(dto);
+    dto.check_author(&author_id)?;
+    check_visited_at(dto.visited_at)?;
+    check_visibility(Some(dto.visibility))?;
+    check_foodnote_images(foodnotes, &author_id, &dto.img_urls).await?;
+    if matches!(&dto.check_in, Some(x) if !x.is_valid()) {
+        return Err(ApiError::BadRequest("invalid check_in".to_string()));
+    }
+
+    let challenges = ChallengesClient::new(&foodnotes.env)?.list_all().await?;
+    let stamp = find_active_stamp(&challenges, &dto.stamp_id, Utc::now().timestamp())?;
+    let check_in = dto.check_in;
+    let mut foodnote = Foodnote::new(&author_id, dto);
+
+    let radius = foodnote.check_stamp(&stamp)?;
+    foodnote.verified_visit = is_verified_visit(check_in.as_ref(), &foodnote.place, radius);
+
+    foodnotes.create(foodnote, &challenges).await
+}
+
+/// A visit can't be in the future, give or take the clock of the client.
+fn check_visited_at(visited_at: Option<i64>) -> ApiResult<()> {
+    match visited_at {
+        Some(x) if x < 0 || x > Utc::now().timestamp() + 60 * 60 * 24 => {
+            Err(ApiError::BadRequest("invalid visited_at".to_string()))
+        }
+        _ => Ok(()),
+    }
+}
+
+fn check_visibility(visibility: Option<Visibility>) -> ApiResult<()> {
+    match visibility {
+        Some(x) if !x.is_accepted() => Err(ApiError::BadRequest("invalid visibility".to_string())),
+        _ => Ok(()),
+    }
+}
+
+/// Foodnotes may only refer to images their author uploaded, so the images can't rot away.
+async fn check_foodnote_images(
+    foodnotes: &Foodnotes,
+    author_id: &str,
+    img_urls: &[String],
+) -> ApiResult<()> {
+    if img_urls.is_empty() {
+        return Ok(());
+    }
+
+    check_uploaded_images(&R2Bucket::new(&foodnotes.env)?, author_id, img_urls).await
+}
+
+/// Stores the image in the body under the prefix of the uploader, to refer to from foodnotes.
+pub async fn upload_my_image(foodnotes: &Foodnotes, mut req: Request) -> ApiResult<ImageDto> {
+    let user_id = get_identity(&foodnotes.env, &req)?.user_id;
+    let bytes = req.bytes().await?;
+    let bucket = R2Bucket::new(&foodnotes.env)?;
+    let (key, image) = upload_image(&bucket, &user_id, bytes).await?;
+
+    let mut url = req.url()?;
+    url.set_path(&key);
+    url.set_query(None);
+
+    Ok(ImageDto {
+        key,
+        url: url.to_string(),
+        content_type: image.content_type,
+        size: image.bytes.len(),
+        captured_at: image.captured_at,
+    })
+}
+
+/// Recent public foodnotes with who wrote them. The same for everyone, so it is cacheable.
+pub async fn get_feed(foodnotes: &Foodnotes, req: Request) -> ApiResult<Page<FeedItemDto>> {
+    let limit = parse_page_limit(req.query_param("limit")?.as_deref());
+    let cursor = req.query_param("cursor")?;
+    let page = foodnotes.list_feed(limit, cursor.as_deref()).await?;
+
+    let mut ids = page
+        .items
+        .iter()
+        .map(|x| x.author_id.to_owned())
+        .collect::<Vec<String>>();
+    ids.sort();
+    ids.dedup();
+    let profiles = UsersClient::new(&foodnotes.env)?.list_profiles(ids).await?;
+
+    Ok(FeedItemDto::page(page, &profiles))
+}
+
+/// Anyone with the share token of a link-only foodnote may read it, without signing in.
+pub async fn get_shared_foodnote(
+    foodnotes: &Foodnotes,
+    id: &str,
+    req: Request,
+) -> ApiResult<Foodnote> {
+    let share_token = req.query_param("token")?.unwrap_or_default();
+    let foodnote = foodnotes.get_by_id(id).await?;
+
+    match foodnote.is_shared_with(&share_token) {
+        true => Ok(foodnote),
+        false => Err(ApiError::FoodnoteNotExists),
+    }
+}
+
+/// Others' foodnotes are only found when public, so private ones can't be probed for.
+pub async fn get_foodnote(foodnotes: &Foodnotes, id: &str, req: Request) -> ApiResult<Foodnote> {
+    let user_id = get_identity(&foodnotes.env, &req)?.user_id;
+    let foodnote = foodnotes.get_by_id(id).await?;
+
+    match foodnote.is_visible_to(&user_id) {
+        true => Ok(foodnote),
+        false => Err(ApiError::FoodnoteNotExists),
+    }
+}
+
+async fn get_my_foodnote(foodnotes: &Foodnotes, id: &str, user_id: &str) -> ApiResult<Foodnote> {
+    let foodnote = foodnotes.get_by_id(id).await?;
+
+    if !foodnote.is_visible_to(user_id) {
+        return Err(ApiError::FoodnoteNotExists);
+    }
+    if !foodnote.is_author(user_id) {
+        return Err(ApiError::NotFoodnoteAuthor);
+    }
+
+    Ok(foodnote)
+}
+
+pub async fn update_my_foodnote(
+    foodnotes: &Foodnotes,
+    id: &str,
+    mut req: Request,
+) -> ApiResult<Foodnote> {
+    let user_id = get_identity(&foodnotes.env, &req)?.user_id;
+    let dto = req.parse_json::<UpdateFoodnoteDto>().await?;
+    check_visited_at(dto.visited_at)?;
+    check_visibility(dto.visibility)?;
+    let mut foodnote = get_my_foodnote(foodnotes, id, &user_id).await?;
+    if let Some(img_urls) = &dto.img_urls {
+        check_foodnote_images(foodnotes, &user_id, img_urls).await?;
+    }
+    let previous = foodnote.clone();
+    foodnote.update(&dto);
+
+    // note: the visit stays verified as the place doesn't change, only the stamp's rules may.
+    let mut challenges = vec![];
+    if foodnote.stamp_id != previous.stamp_id {
+        challenges = ChallengesClient::new(&foodnotes.env)?.list_all().await?;
+        let stamp = find_active_stamp(&challenges, &foodnote.stamp_id, Utc::now().timestamp())?;
+        foodnote.check_stamp(&stamp)?;
+    }
+
+    foodnotes.update(&foodnote, &previous, &challenges).await?;
+
+    Ok(foodnote)
+}
+
+pub async fn delete_my_foodnote(
+    foodnotes: &Foodnotes,
+    id: &str,
+    req: Request,
+) -> ApiResult<Foodnote> {
+    let user_id = get_identity(&foodnotes.env, &req)?.user_id;
+    let foodnote = get_my_foodnote(foodnotes, id, &user_id).await?;
+    let challenges = ChallengesClient::new(&foodnotes.env)?.list_all().await?;
+
+    foodnotes.delete(&foodnote, &challenges).await?;
+
+    Ok(foodnote)
+}
+
+/// Redeems a partner's claim code for a foodnote with a verified visit to the partner's place.
+pub async fn claim_stamp(foodnotes: &Foodnotes, mut req: Request) -> ApiResult<Foodnote> {
+    let author_id = get_identity(&foodnotes.env, &req)?.user_id;
+    let dto = req.parse_json::<ClaimStampDto>().await?;
+
+    let jwt = get_jwt_for_claim_codes(&foodnotes.env)?;
+    let claim_code = verify_claim_code(&jwt, &dto.code, Utc::now().timestamp())?;
+
+    let mut foodnote = Foodnote::new(
+        &author_id,
+        CreateFoodnoteDto {
+            stamp_id: claim_code.stamp_id.to_owned(),
+            author_id: None,
+            text: "".to_string(),
+            place: claim_code.place.to_owned(),
+            visited_at: None,
+            img_urls: vec![],
+            visibility: Visibility::Private,
+            check_in: None,
+        },
+    );
+    foodnote.verified_visit = true;
+
+    // note: other requests only run while fetching, so redeem before fetching the stamp.
+    let mut storage = foodnotes.state.storage();
+    let redemption_key = claim_code.redemption_key(&author_id);
+    if storage.find::<Redemption>(&redemption_key).await?.is_some() {
+        return Err(ApiError::ClaimCodeRedeemed);
+    }
+    let redemption = Redemption {
+        user_id: author_id,
+        foodnote_id: foodnote.id.to_owned(),
+        redeemed_at: foodnote.timestamp,
+    };
+    storage.put(&redemption_key, &redemption).await?;
+
+    // note: the constraints of the stamp may have changed since the code was issued.
+    let challenges = match ChallengesClient::new(&foodnotes.env)?.list_all().await {
+        Ok(x) => x,
+        Err(e) => {
+            storage.delete(&redemption_key).await?;
+            return Err(e);
+        }
+    };
+    let checked = find_active_stamp(&challenges, &foodnote.stamp_id, foodnote.timestamp)
+        .and_then(|stamp| foodnote.check_stamp(&stamp));
+    if let Err(e) = checked {
+        storage.delete(&redemption_key).await?;
+        return Err(e);
+    }
+
+    match foodnotes.create(foodnote, &challenges).await {
+        Ok(x) => Ok(x),
+        Err(e) => {
+            storage.delete(&redemption_key).await?;
+            Err(e)
+        }
+    }
+}
+
+pub async fn list_my_challenge_progress(
+    foodnotes: &Foodnotes,
+    req: Request,
+) -> ApiResult<Vec<ChallengeProgress>> {
+    let author_id = get_identity(&foodnotes.env, &req)?.user_id;
+    let challenges = ChallengesClient::new(&foodnotes.env)?.list_all().await?;
+    let collected = foodnotes.list_collected_stamps(&author_id).await?;
+    let now = Utc::now().timestamp();
+
+    Ok(list_active_progress(&challenges, &collected, now))
+}
+
+pub async fn get_my_challenge_progress(
+    foodnotes: &Foodnotes,
+    challenge_id: &str,
+    req: Request,
+) -> ApiResult<ChallengeProgress> {
+    let author_id = get_identity(&foodnotes.env, &req)?.user_id;
+    let challenge = ChallengesClient::new(&foodnotes.env)?
+        .get_by_id(challenge_id)
+        .await?;
+    let collected = foodnotes.list_collected_stamps(&author_id).await?;
+
+    Ok(ChallengeProgress::new(&challenge, &collected))
+}
+
+pub async fn list_my_badges(foodnotes: &Foodnotes, req: Request) -> ApiResult<Vec<BadgeDto>> {
+    let author_id = get_identity(&foodnotes.env, &req)?.user_id;
+
+    // note: catches up on challenges completed before badges existed.
+    let challenges = ChallengesClient::new(&foodnotes.env)?.list_all().await?;
+    foodnotes.award_badges(&author_id, &challenges).await?;
+
+    let badges = foodnotes
+        .list_badges(&author_id)
+        .await?
+        .into_iter()
+        .map(|badge| {
+            let challenge = challenges.iter().find(|x| x.id == badge.challenge_id);
+            BadgeDto::new(badge, challenge)
+        })
+        .collect();
+
+    Ok(badges)
+}
+
+pub async fn get_global_leaderboard(foodnotes: &Foodnotes, req: Request) -> ApiResult<Leaderboard> {
+    let user_id = get_identity(&foodnotes.env, &req)?.user_id;
+    let limit = parse_leaderboard_limit(req.query_param("limit")?.as_deref());
+
+    foodnotes
+        .get_leaderboard(GLOBAL_RANK_PREFIX, &user_id, limit)
+        .await
+}
+
+pub async fn get_challenge_leaderboard(
+    foodnotes: &Foodnotes,
+    challenge_id: &str,
+    req: Request,
+) -> ApiResult<Leaderboard> {
+    let user_id = get_identity(&foodnotes.env, &req)?.user_id;
+    let limit = parse_leaderboard_limit(req.query_param("limit")?.as_deref());
+
+    // note: only to answer 404 for unknown challenges.
+    ChallengesClient::new(&foodnotes.env)?
+        .get_by_id(challenge_id)
+        .await?;
+
+    foodnotes
+        .get_leaderboard(&challenge_ranks_prefix(challenge_id), &user_id, limit)
+        .await
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LeaderboardSettingsDto {
+    pub opt_out: bool,
+}
+
+pub async fn update_my_leaderboard_settings(
+    foodnotes: &Foodnotes,
+    mut req: Request,
+) -> ApiResult<LeaderboardSettingsDto> {
+    let user_id = get_identity(&foodnotes.env, &req)?.user_id;
+    let dto = req.parse_json::<LeaderboardSettingsDto>().await?;
+
+    foodnotes
+        .set_leaderboard_opt_out(&user_id, dto.opt_out)
+        .await?;
 
-    foodnotes.create(foodnote).await
+    Ok(dto)
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StampReferencesDto {
+    pub stamp_ids: Vec<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RefreshChallengesDto {
+    pub challenges: Vec<Challenge>,
+}
+
+pub async fn refresh_challenges(foodnotes: &Foodnotes, mut req: Request) -> ApiResult<usize> {
+    let dto = req.parse_json::<RefreshChallengesDto>().await?;
+
+    foodnotes.refresh_challenges(&dto.challenges).await
+}
+
+pub async fn check_stamp_references(
+    foodnotes: &Foodnotes,
+    mut req: Request,
+) -> ApiResult<StampReferencesDto> {
+    let dto = req.parse_json::<StampReferencesDto>().await?;
+    let stamp_ids = foodnotes.list_referenced_stamps(&dto.stamp_ids).await?;
+
+    Ok(StampReferencesDto { stamp_ids })
 }
 
 #[durable_object]
@@ -166,10 +1581,14 @@ impl DurableObject for Foodnotes {
     async fn fetch(&mut self, req: Request) -> Result<Response> {
         let method = req.method();
         let path = req.path();
+        let segments = path_segments(&path);
 
         if method == Method::Get && &path == "/foodnotes" {
             return match list_my_foodnotes(self, req).await {
-                Ok(foodnotes) => response(&json!({ "foodnotes": foodnotes })),
+                Ok(page) => response(&json!({
+                    "foodnotes": page.items,
+                    "next_cursor": page.next_cursor,
+                })),
                 Err(e) => Ok(e.to_response()),
             };
         }
@@ -181,6 +1600,331 @@ impl DurableObject for Foodnotes {
             };
         }
 
+        // GET /feed
+        if method == Method::Get && &path == "/feed" {
+            return match get_feed(self, req).await {
+                Ok(page) => response_with_cache(
+                    &json!({ "foodnotes": page.items, "next_cursor": page.next_cursor }),
+                    FEED_CACHE_MAX_AGE,
+                ),
+                Err(e) => Ok(e.to_response()),
+            };
+        }
+
+        // GET /shared/foodnotes/:id
+        if let (Method::Get, ["shared", "foodnotes", id]) = (&method, segments.as_slice()) {
+            return match get_shared_foodnote(self, id, req).await {
+                Ok(foodnote) => response(&foodnote),
+                Err(e) => Ok(e.to_response()),
+            };
+        }
+
+        // GET /foodnotes/:id
+        if let (Method::Get, ["foodnotes", id]) = (&method, segments.as_slice()) {
+            return match get_foodnote(self, id, req).await {
+                Ok(foodnote) => response(&foodnote),
+                Err(e) => Ok(e.to_response()),
+            };
+        }
+
+        // PATCH /foodnotes/:id
+        if let (Method::Patch, ["foodnotes", id]) = (&method, segments.as_slice()) {
+            return match update_my_foodnote(self, id, req).await {
+                Ok(foodnote) => response(&foodnote),
+                Err(e) => Ok(e.to_response()),
+            };
+        }
+
+        // DELETE /foodnotes/:id
+        if let (Method::Delete, ["foodnotes", id]) = (&method, segments.as_slice()) {
+            return match delete_my_foodnote(self, id, req).await {
+                Ok(foodnote) => response(&foodnote),
+                Err(e) => Ok(e.to_response()),
+            };
+        }
+
+        // POST /images
+        if method == Method::Post && &path == "/images" {
+            return match upload_my_image(self, req).await {
+                Ok(image) => response(&image),
+                Err(e) => Ok(e.to_response()),
+            };
+        }
+
+        // POST /stamps/claim
+        if method == Method::Post && &path == "/stamps/claim" {
+            return match claim_stamp(self, req).await {
+                Ok(foodnote) => response(&foodnote),
+                Err(e) => Ok(e.to_response()),
+            };
+        }
+
+        // GET /me/challenges/progress
+        if method == Method::Get && &path == "/me/challenges/progress" {
+            return match list_my_challenge_progress(self, req).await {
+                Ok(progress) => response(&json!({ "progress": progress })),
+                Err(e) => Ok(e.to_response()),
+            };
+        }
+
+        // GET /me/challenges/:id/progress
+        if let (Method::Get, ["me", "challenges", id, "progress"]) = (&method, segments.as_slice())
+        {
+            return match get_my_challenge_progress(self, id, req).await {
+                Ok(progress) => response(&progress),
+                Err(e) => Ok(e.to_response()),
+            };
+        }
+
+        // GET /me/badges
+        if method == Method::Get && &path == "/me/badges" {
+            return match list_my_badges(self, req).await {
+                Ok(badges) => response(&json!({ "badges": badges })),
+                Err(e) => Ok(e.to_response()),
+            };
+        }
+
+        // GET /leaderboards/global
+        if method == Method::Get && &path == "/leaderboards/global" {
+            return match get_global_leaderboard(self, req).await {
+                Ok(leaderboard) => response(&leaderboard),
+                Err(e) => Ok(e.to_response()),
+            };
+        }
+
+        // GET /leaderboards/challenges/:id
+        if let (Method::Get, ["leaderboards", "challenges", id]) = (&method, segments.as_slice()) {
+            return match get_challenge_leaderboard(self, id, req).await {
+                Ok(leaderboard) => response(&leaderboard),
+                Err(e) => Ok(e.to_response()),
+            };
+        }
+
+        // PUT /me/leaderboard
+        if method == Method::Put && &path == "/me/leaderboard" {
+            return match update_my_leaderboard_settings(self, req).await {
+                Ok(settings) => response(&settings),
+                Err(e) => Ok(e.to_response()),
+            };
+        }
+
+        // POST /internal/stamps/references
+        if method == Method::Post && &path == "/internal/stamps/references" {
+            return match check_stamp_references(self, req).await {
+                Ok(referenced) => response(&referenced),
+                Err(e) => Ok(e.to_response()),
+            };
+        }
+
+        // POST /internal/challenges/refresh
+        if method == Method::Post && &path == "/internal/challenges/refresh" {
+            return match refresh_challenges(self, req).await {
+                Ok(authors) => response(&json!({ "authors": authors })),
+                Err(e) => Ok(e.to_response()),
+            };
+        }
+
         Response::error("not found", 404)
     }
 }
+
+#[cfg(test)]
+mod foodnote_tests {
+    use super::*;
+    use crate::test_fixtures::{challenge, foodnote, place};
+
+    #[test]
+    fn should_sort_author_index_keys_by_time() {
+        let earlier = author_index_key("alice", 999_999_999, "b");
+        let later = author_index_key("alice", 1_000_000_000, "a");
+
+        assert!(earlier < later);
+        assert!(later.starts_with(&author_index_prefix("alice")));
+        assert!(!foodnote_author_id_key("alice").starts_with(&author_index_prefix("alice")));
+    }
+
+    #[test]
+    fn should_not_list_references_of_stamps_sharing_a_prefix() {
+        let key = stamp_reference_key("ribeye_roll", "a");
+
+        assert!(key.starts_with(&stamp_references_prefix("ribeye_roll")));
+        assert!(!key.starts_with(&stamp_references_prefix("ribeye")));
+    }
+
+    #[test]
+    fn should_filter_foodnotes() {
+        let mut public = foodnote("a", 10);
+        public.set_visibility(Visibility::Public);
+        let filter = FoodnoteFilter {
+            stamp_id: Some("a".to_string()),
+            from: Some(5),
+            to: Some(10),
+            visibility: Some(Visibility::Public),
+        };
+
+        assert!(filter.matches(&public));
+        assert!(!filter.matches(&foodnote("a", 10)));
+        assert!(!filter.matches(&foodnote("b", 10)));
+        assert!(!FoodnoteFilter {
+            to: Some(9),
+            ..Default::default()
+        }
+        .matches(&public));
+    }
+
+    #[test]
+    fn should_range_author_index_by_time() {
+        let filter = FoodnoteFilter {
+            from: Some(10),
+            to: Some(20),
+            ..Default::default()
+        };
+        let range = filter.key_range("alice");
+        let start = range.start.unwrap();
+        let end = range.end.unwrap();
+
+        assert!(start.as_str() <= author_index_key("alice", 10, "a").as_str());
+        assert!(end.as_str() > author_index_key("alice", 20, "zzz").as_str());
+        assert!(end.as_str() <= author_index_key("alice", 21, "a").as_str());
+    }
+
+    #[test]
+    fn should_update_only_given_fields() {
+        let mut foodnote = foodnote("a", 10);
+        foodnote.update(&UpdateFoodnoteDto {
+            text: Some("맛있어요".to_string()),
+            visibility: Some(Visibility::Public),
+            ..Default::default()
+        });
+
+        assert_eq!(foodnote.stamp_id, "a");
+        assert_eq!(foodnote.text, "맛있어요");
+        assert!(foodnote.is_public());
+        assert!(foodnote.img_urls.is_empty());
+    }
+
+    #[test]
+    fn should_update_visited_at() {
+        let mut foodnote = foodnote("a", 10);
+        assert_eq!(foodnote.visited_at, None);
+
+        foodnote.update(&UpdateFoodnoteDto {
+            visited_at: Some(5),
+            ..Default::default()
+        });
+        assert_eq!(foodnote.visited_at, Some(5));
+
+        assert!(check_visited_at(Some(5)).is_ok());
+        assert!(check_visited_at(Some(Utc::now().timestamp() + 60 * 60 * 24 * 7)).is_err());
+    }
+
+    fn create_dto(author_id: Option<&str>) -> CreateFoodnoteDto {
+        CreateFoodnoteDto {
+            stamp_id: "a".to_string(),
+            author_id: author_id.map(|x| x.to_string()),
+            text: "".to_string(),
+            place: place(),
+            visited_at: None,
+            img_urls: vec![],
+            visibility: Visibility::Private,
+            check_in: None,
+        }
+    }
+
+    #[test]
+    fn should_take_author_from_authenticated_user() {
+        let dto = create_dto(None);
+        dto.check_author("alice").unwrap();
+
+        assert_eq!(Foodnote::new("alice", dto).author_id, "alice");
+    }
+
+    #[test]
+    fn should_accept_author_matching_authenticated_user() {
+        assert!(create_dto(Some("alice")).check_author("alice").is_ok());
+    }
+
+    #[test]
+    fn should_reject_forged_author() {
+        let result = create_dto(Some("bob")).check_author("alice");
+
+        assert!(matches!(result, Err(ApiError::NotFoodnoteAuthor)));
+    }
+
+    #[test]
+    fn should_not_deserialize_author_into_foodnote() {
+        let dto = serde_json::from_value::<CreateFoodnoteDto>(json!({
+            "stamp_id": "a",
+            "author_id": "bob",
+            "text": "",
+            "place": place(),
+            "img_urls": [],
+            "is_public": false,
+            "check_in": null
+        }))
+        .unwrap();
+
+        assert_eq!(Foodnote::new("alice", dto).author_id, "alice");
+    }
+
+    #[test]
+    fn should_be_visible_to_author_or_when_public() {
+        let mut foodnote = foodnote("a", 10);
+
+        assert!(foodnote.is_visible_to("alice"));
+        assert!(!foodnote.is_visible_to("bob"));
+
+        foodnote.set_visibility(Visibility::Public);
+        assert!(foodnote.is_visible_to("bob"));
+        assert!(!foodnote.is_author("bob"));
+
+        for visibility in [Visibility::Followers, Visibility::LinkOnly] {
+            foodnote.set_visibility(visibility);
+            assert!(!foodnote.is_visible_to("bob"));
+        }
+    }
+
+    #[test]
+    fn should_share_link_only_foodnote_by_token() {
+        let mut foodnote = foodnote("a", 10);
+        foodnote.set_visibility(Visibility::LinkOnly);
+        let share_token = foodnote.share_token.clone().unwrap();
+
+        assert_eq!(share_token.len(), 32);
+        assert!(foodnote.is_shared_with(&share_token));
+        assert!(!foodnote.is_shared_with("guess"));
+
+        // note: the token stays while link-only, and is revoked after.
+        foodnote.set_visibility(Visibility::LinkOnly);
+        assert_eq!(foodnote.share_token.as_deref(), Some(share_token.as_str()));
+        foodnote.set_visibility(Visibility::Private);
+        assert!(foodnote.share_token.is_none());
+        assert!(!foodnote.is_shared_with(&share_token));
+    }
+
+    #[test]
+    fn should_update_visibility_from_older_clients() {
+        let mut foodnote = foodnote("a", 10);
+        foodnote.update(&UpdateFoodnoteDto {
+            is_public: Some(true),
+            ..Default::default()
+        });
+
+        assert_eq!(foodnote.visibility, Visibility::Public);
+    }
+
+    #[test]
+    fn should_clear_flags_for_stamp_without_constraints() {
+        let mut foodnote = foodnote("a", 10);
+        foodnote.place_mismatch = true;
+        foodnote.verified_visit_required = true;
+        let stamp = challenge(&["b"]).stamps[0].clone();
+
+        assert_eq!(
+            foodnote.check_stamp(&stamp).unwrap(),
+            DEFAULT_CHECK_IN_RADIUS_METERS
+        );
+        assert!(!foodnote.place_mismatch);
+        assert!(!foodnote.verified_visit_required);
+    }
+}
@@ -0,0 +1,84 @@
+use serde::{Deserialize, Serialize};
+
+use crate::place::PlaceDocument;
+
+const EARTH_RADIUS_METERS: f64 = 6_371_000.0;
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct GeoPoint {
+    pub latitude: f64,
+    pub longitude: f64,
+}
+
+impl GeoPoint {
+    pub fn is_valid(&self) -> bool {
+        (-90.0..=90.0).contains(&self.latitude) && (-180.0..=180.0).contains(&self.longitude)
+    }
+
+    /// Kakao places carry their longitude in `x` and latitude in `y`.
+    pub fn of_place(place: &PlaceDocument) -> Option<Self> {
+        let point = Self {
+            latitude: place.y.parse::<f64>().ok()?,
+            longitude: place.x.parse::<f64>().ok()?,
+        };
+
+        match point.is_valid() {
+            true => Some(point),
+            false => None,
+        }
+    }
+
+    /// Great-circle distance in meters, using the haversine formula.
+    pub fn distance_to(&self, other: &GeoPoint) -> f64 {
+        let lat1 = self.latitude.to_radians();
+        let lat2 = other.latitude.to_radians();
+        let d_lat = (other.latitude - self.latitude).to_radians();
+        let d_lon = (other.longitude - self.longitude).to_radians();
+
+        let a = (d_lat / 2.0).sin().powi(2) + lat1.cos() * lat2.cos() * (d_lon / 2.0).sin().powi(2);
+        let c = 2.0 * a.sqrt().atan2((1.0 - a).sqrt());
+
+        EARTH_RADIUS_METERS * c
+    }
+}
+
+#[cfg(test)]
+mod geo_tests {
+    use super::*;
+    use crate::test_fixtures::place;
+
+    #[test]
+    fn should_read_place_coordinates() {
+        let point = GeoPoint::of_place(&place()).unwrap();
+
+        assert!((point.latitude - 37.5266092359544).abs() < 1e-9);
+        assert!((point.longitude - 127.035423195622).abs() < 1e-9);
+    }
+
+    #[test]
+    fn should_not_read_invalid_place_coordinates() {
+        let mut place = place();
+        place.x = "".to_string();
+        assert!(GeoPoint::of_place(&place).is_none());
+
+        place.x = "127.0".to_string();
+        place.y = "137.0".to_string();
+        assert!(GeoPoint::of_place(&place).is_none());
+    }
+
+    #[test]
+    fn should_calculate_haversine_distance() {
+        let seoul_station = GeoPoint {
+            latitude: 37.5547,
+            longitude: 126.9707,
+        };
+        let gangnam_station = GeoPoint {
+            latitude: 37.4979,
+            longitude: 127.0276,
+        };
+        let distance = seoul_station.distance_to(&gangnam_station);
+
+        assert!((distance - 8_040.0).abs() < 100.0, "distance: {}", distance);
+        assert_eq!(seoul_station.distance_to(&seoul_station), 0.0);
+    }
+}
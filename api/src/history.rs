@@ -0,0 +1,166 @@
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+
+use crate::challenges::Challenge;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ChallengeVersion {
+    pub version: u32,
+    pub editor_id: Option<String>,
+    pub edited_at: i64,
+    /// The challenge as this version stored it; `None` when it deleted the challenge.
+    pub challenge: Option<Challenge>,
+    pub changes: Vec<FieldChange>,
+    /// The version restored by this one, when it is a rollback.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rolled_back_to: Option<u32>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RollbackDto {
+    pub version: u32,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FieldChange {
+    pub field: String,
+    pub before: Value,
+    pub after: Value,
+}
+
+/// Field changes from `before` to `after`. Stamps are compared by id, e.g. `stamps.sirloin.title`,
+/// with `stamp_ids` changing when stamps are added, removed or reordered.
+pub fn diff_challenges(before: Option<&Challenge>, after: &Challenge) -> Vec<FieldChange> {
+    let before = before
+        .and_then(|x| serde_json::to_value(x).ok())
+        .unwrap_or(Value::Null);
+    let after = serde_json::to_value(after).unwrap_or(Value::Null);
+    let mut changes = Vec::<FieldChange>::new();
+
+    let empty = Map::new();
+    let before_fields = before.as_object().unwrap_or(&empty);
+    let after_fields = after.as_object().unwrap_or(&empty);
+    let mut keys = before_fields
+        .keys()
+        .chain(after_fields.keys())
+        .collect::<Vec<&String>>();
+    keys.sort();
+    keys.dedup();
+
+    for key in keys {
+        let before = before_fields.get(key).unwrap_or(&Value::Null);
+        let after = after_fields.get(key).unwrap_or(&Value::Null);
+        match key.as_str() {
+            "stamps" => diff_stamps(before, after, &mut changes),
+            _ => push_change(key, before, after, &mut changes),
+        }
+    }
+
+    changes
+}
+
+fn diff_stamps(before: &Value, after: &Value, changes: &mut Vec<FieldChange>) {
+    let by_id = |stamps: &Value| -> Vec<(String, Value)> {
+        stamps
+            .as_array()
+            .map(|x| {
+                x.iter()
+                    .map(|x| (x["id"].as_str().unwrap_or_default().to_owned(), x.clone()))
+                    .collect()
+            })
+            .unwrap_or_default()
+    };
+    let before = by_id(before);
+    let after = by_id(after);
+
+    let ids = |stamps: &[(String, Value)]| {
+        Value::from(
+            stamps
+                .iter()
+                .map(|(id, _)| id.to_owned())
+                .collect::<Vec<String>>(),
+        )
+    };
+    push_change("stamp_ids", &ids(&before), &ids(&after), changes);
+
+    for (id, after_stamp) in &after {
+        let before_stamp = before
+            .iter()
+            .find(|(x, _)| x == id)
+            .map(|(_, x)| x.clone())
+            .unwrap_or(Value::Null);
+
+        let empty = Map::new();
+        let before_fields = before_stamp.as_object().unwrap_or(&empty);
+        let after_fields = after_stamp.as_object().unwrap_or(&empty);
+        let mut keys = before_fields
+            .keys()
+            .chain(after_fields.keys())
+            .collect::<Vec<&String>>();
+        keys.sort();
+        keys.dedup();
+
+        for key in keys.into_iter().filter(|x| *x != "id") {
+            push_change(
+                &format!("stamps.{}.{}", id, key),
+                before_fields.get(key).unwrap_or(&Value::Null),
+                after_fields.get(key).unwrap_or(&Value::Null),
+                changes,
+            );
+        }
+    }
+}
+
+fn push_change(field: &str, before: &Value, after: &Value, changes: &mut Vec<FieldChange>) {
+    if before != after {
+        changes.push(FieldChange {
+            field: field.to_owned(),
+            before: before.clone(),
+            after: after.clone(),
+        });
+    }
+}
+
+#[cfg(test)]
+mod history_tests {
+    use super::*;
+    use crate::test_fixtures::challenge;
+
+    #[test]
+    fn should_diff_every_field_of_new_challenge() {
+        let changes = diff_challenges(None, &challenge(&["a"]));
+
+        assert!(changes
+            .iter()
+            .any(|x| x.field == "name" && x.before == Value::Null));
+        assert!(changes.iter().any(|x| x.field == "stamps.a.title"));
+    }
+
+    #[test]
+    fn should_not_diff_same_challenge() {
+        let challenge = challenge(&["a", "b"]);
+
+        assert!(diff_challenges(Some(&challenge), &challenge).is_empty());
+    }
+
+    #[test]
+    fn should_diff_added_and_reordered_stamps() {
+        let before = challenge(&["ribeye_roll", "sirloin"]);
+        let mut after = before.clone();
+        after.stamps.reverse();
+        after
+            .stamps
+            .push(challenge(&["tenderloin"]).stamps.remove(0));
+
+        let changes = diff_challenges(Some(&before), &after);
+
+        assert_eq!(changes[0].field, "stamp_ids");
+        assert_eq!(
+            changes[0].after,
+            serde_json::json!(["sirloin", "ribeye_roll", "tenderloin"])
+        );
+        assert!(changes
+            .iter()
+            .any(|x| x.field == "stamps.tenderloin.title" && x.before == Value::Null));
+    }
+}
@@ -0,0 +1,154 @@
+use chrono::Duration;
+use serde::{Deserialize, Serialize};
+use worker::{Env, Method, Request};
+
+use crate::api_error::ApiError;
+use crate::api_result::ApiResult;
+use crate::jwt::Jwt;
+
+pub const IDENTITY_HEADER: &str = "X-Foodrhapsody-Identity";
+pub const IDENTITY_SECRET: &str = "IDENTITY_SECRET";
+/// Only long enough for the gateway to reach a durable object, plus the leeway verifying allows.
+pub const IDENTITY_TTL_SECONDS: i64 = 30;
+
+/// Headers only the gateway may set. Anything a client sends under these names is dropped.
+const IDENTITY_HEADERS: [&str; 2] = [IDENTITY_HEADER, "X-Foodrhapsody-User"];
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Role {
+    User,
+    Admin,
+}
+
+/// Who a request is made by, asserted by the gateway after authenticating it.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Identity {
+    #[serde(rename = "sub")]
+    pub user_id: String,
+    pub roles: Vec<Role>,
+    pub request_id: String,
+}
+
+impl Identity {
+    pub fn new(user_id: &str, roles: &[Role], request_id: &str) -> Self {
+        Self {
+            user_id: user_id.to_owned(),
+            roles: roles.to_vec(),
+            request_id: request_id.to_owned(),
+        }
+    }
+
+    pub fn is_admin(&self) -> bool {
+        self.roles.contains(&Role::Admin)
+    }
+}
+
+pub fn is_identity_header(name: &str) -> bool {
+    IDENTITY_HEADERS
+        .iter()
+        .any(|x| x.eq_ignore_ascii_case(name))
+}
+
+pub fn get_jwt_for_identity(env: &Env) -> ApiResult<Jwt> {
+    let secret = env.secret(IDENTITY_SECRET)?;
+
+    Ok(Jwt::new(&secret.to_string()))
+}
+
+pub fn sign_identity(jwt: &Jwt, identity: &Identity) -> ApiResult<String> {
+    let claims = jwt.create_claims(identity, Duration::seconds(IDENTITY_TTL_SECONDS));
+
+    Ok(jwt.sign(&claims)?)
+}
+
+pub fn verify_identity(jwt: &Jwt, assertion: &str) -> ApiResult<Identity> {
+    match jwt.verify::<Identity>(assertion) {
+        Ok(token) => Ok(token.claims().custom.clone()),
+        Err(_) => Err(ApiError::Unauthorized),
+    }
+}
+
+/// The identity asserted for the request, `None` for anonymous requests.
+pub fn find_identity(env: &Env, req: &Request) -> ApiResult<Option<Identity>> {
+    match req.headers().get(IDENTITY_HEADER)? {
+        Some(assertion) => {
+            let jwt = get_jwt_for_identity(env)?;
+            Ok(Some(verify_identity(&jwt, &assertion)?))
+        }
+        None => Ok(None),
+    }
+}
+
+pub fn get_identity(env: &Env, req: &Request) -> ApiResult<Identity> {
+    match find_identity(env, req)? {
+        Some(identity) => Ok(identity),
+        None => Err(ApiError::Unauthorized),
+    }
+}
+
+/// Routes of the challenges the gateway only passes through for admins.
+pub fn is_admin_route(method: &Method, segments: &[&str]) -> bool {
+    match (method, segments) {
+        (_, ["internal", ..]) => false,
+        (Method::Post | Method::Delete, ["challenges", _, "join"]) => false,
+        (Method::Get, ["challenges", "export"]) => true,
+        (Method::Get, ["challenges", _, "history"]) => true,
+        (Method::Get, _) => false,
+        _ => true,
+    }
+}
+
+#[cfg(test)]
+mod identity_tests {
+    use super::*;
+
+    fn identity() -> Identity {
+        Identity::new("alice", &[Role::User], "request")
+    }
+
+    #[test]
+    fn should_verify_signed_identity() {
+        let jwt = Jwt::new("this_is_secret");
+        let assertion = sign_identity(&jwt, &identity()).unwrap();
+
+        assert_eq!(verify_identity(&jwt, &assertion).unwrap(), identity());
+    }
+
+    #[test]
+    fn should_not_verify_identity_with_other_secret() {
+        let assertion = sign_identity(&Jwt::new("forged"), &identity()).unwrap();
+        let verified = verify_identity(&Jwt::new("this_is_secret"), &assertion);
+
+        assert!(matches!(verified.unwrap_err(), ApiError::Unauthorized));
+    }
+
+    #[test]
+    fn should_not_verify_expired_identity() {
+        let jwt = Jwt::new("this_is_secret");
+        let claims = jwt.create_claims(identity(), Duration::hours(-1));
+        let assertion = jwt.sign(&claims).unwrap();
+
+        assert!(verify_identity(&jwt, &assertion).is_err());
+    }
+
+    #[test]
+    fn should_match_identity_headers_case_insensitively() {
+        assert!(is_identity_header("x-foodrhapsody-user"));
+        assert!(is_identity_header("X-Foodrhapsody-Identity"));
+        assert!(!is_identity_header("authorization"));
+    }
+
+    #[test]
+    fn should_tell_admin_routes() {
+        assert!(is_admin_route(&Method::Post, &["challenges"]));
+        assert!(is_admin_route(&Method::Get, &["challenges", "export"]));
+        assert!(is_admin_route(
+            &Method::Get,
+            &["challenges", "a", "history"]
+        ));
+        assert!(!is_admin_route(&Method::Get, &["challenges", "a"]));
+        assert!(!is_admin_route(&Method::Post, &["challenges", "a", "join"]));
+        assert!(!is_admin_route(&Method::Get, &["internal", "challenges"]));
+    }
+}
@@ -0,0 +1,665 @@
+use std::convert::TryInto;
+
+use chrono::{DateTime, NaiveDateTime};
+
+use crate::api_error::ApiError;
+use crate::api_result::ApiResult;
+use crate::images::ImageKind;
+
+const EXIF_HEADER: &[u8] = b"Exif\0\0";
+const PNG_SIGNATURE: [u8; 8] = [0x89, b'P', b'N', b'G', 0x0d, 0x0a, 0x1a, 0x0a];
+/// Chunks of a PNG with text, times or EXIF a camera or an editor may have written.
+const PNG_METADATA_CHUNKS: [&[u8; 4]; 5] = [b"eXIf", b"tEXt", b"zTXt", b"iTXt", b"tIME"];
+const WEBP_EXIF_FLAG: u8 = 0x08;
+const WEBP_XMP_FLAG: u8 = 0x04;
+
+const TAG_ORIENTATION: u16 = 0x0112;
+const TAG_DATE_TIME: u16 = 0x0132;
+const TAG_EXIF_IFD: u16 = 0x8769;
+const TAG_DATE_TIME_ORIGINAL: u16 = 0x9003;
+const TAG_OFFSET_TIME_ORIGINAL: u16 = 0x9011;
+
+/// An image without its metadata, and what is kept of it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CleanImage {
+    pub bytes: Vec<u8>,
+    /// When the photo was taken, as a timestamp. Cameras not recording their offset are taken
+    /// as UTC.
+    pub captured_at: Option<i64>,
+}
+
+/// What is read of the EXIF of an image before it is dropped.
+#[derive(Debug, Clone, Default, PartialEq)]
+struct ExifSummary {
+    orientation: Option<u16>,
+    captured_at: Option<i64>,
+}
+
+impl ExifSummary {
+    fn merge(&mut self, other: ExifSummary) {
+        self.orientation = self.orientation.or(other.orientation);
+        self.captured_at = self.captured_at.or(other.captured_at);
+    }
+
+    /// Only an orientation other than the default is worth keeping, or photos show rotated.
+    fn kept_exif(&self) -> Option<Vec<u8>> {
+        match self.orientation {
+            Some(x) if (2..=8).contains(&x) => Some(orientation_exif(x)),
+            _ => None,
+        }
+    }
+}
+
+/// Drops EXIF, XMP and other metadata that may tell where a photo was taken and with what,
+/// keeping only the orientation.
+pub fn strip_metadata(kind: ImageKind, bytes: &[u8]) -> ApiResult<CleanImage> {
+    let stripped = match kind {
+        ImageKind::Jpeg => strip_jpeg(bytes),
+        ImageKind::Png => strip_png(bytes),
+        ImageKind::Webp => strip_webp(bytes),
+    };
+
+    match stripped {
+        Some((bytes, exif)) => Ok(CleanImage {
+            bytes,
+            captured_at: exif.captured_at,
+        }),
+        None => Err(ApiError::UnsupportedImage),
+    }
+}
+
+fn read_u16_be(bytes: &[u8], at: usize) -> Option<u16> {
+    Some(u16::from_be_bytes(bytes.get(at..at + 2)?.try_into().ok()?))
+}
+
+fn read_u32_be(bytes: &[u8], at: usize) -> Option<u32> {
+    Some(u32::from_be_bytes(bytes.get(at..at + 4)?.try_into().ok()?))
+}
+
+fn read_u32_le(bytes: &[u8], at: usize) -> Option<u32> {
+    Some(u32::from_le_bytes(bytes.get(at..at + 4)?.try_into().ok()?))
+}
+
+fn strip_jpeg(bytes: &[u8]) -> Option<(Vec<u8>, ExifSummary)> {
+    let mut exif = ExifSummary::default();
+    let mut segments: Vec<&[u8]> = vec![];
+    let mut pos = 2;
+
+    loop {
+        if *bytes.get(pos)? != 0xff {
+            return None;
+        }
+        let marker = *bytes.get(pos + 1)?;
+        match marker {
+            // note: fill bytes before a marker.
+            0xff => {
+                pos += 1;
+                continue;
+            }
+            0xd9 => {
+                // note: anything after the end, like the previews of MPF, goes with their EXIF.
+                segments.push(&bytes[pos..pos + 2]);
+                break;
+            }
+            0x01 | 0xd0..=0xd7 => {
+                segments.push(&bytes[pos..pos + 2]);
+                pos += 2;
+                continue;
+            }
+            _ => {}
+        }
+
+        let end = pos + 2 + read_u16_be(bytes, pos + 2)? as usize;
+        let segment = bytes.get(pos..end)?;
+        let data = segment.get(4..)?;
+
+        if marker == 0xda {
+            let scan_end = find_jpeg_scan_end(bytes, end)?;
+            segments.push(&bytes[pos..scan_end]);
+            pos = scan_end;
+            continue;
+        }
+
+        let is_kept = match marker {
+            0xe0 => true,
+            0xe1 => {
+                if let Some(tiff) = data.strip_prefix(EXIF_HEADER) {
+                    exif.merge(read_exif(tiff));
+                }
+                false
+            }
+            0xe2 => data.starts_with(b"ICC_PROFILE\0"),
+            0xee => true,
+            0xe3..=0xef | 0xfe => false,
+            _ => true,
+        };
+        if is_kept {
+            segments.push(segment);
+        }
+        pos = end;
+    }
+
+    let mut stripped = bytes[..2].to_vec();
+    let app0_count = segments
+        .iter()
+        .take_while(|x| x.get(1) == Some(&0xe0))
+        .count();
+    for segment in &segments[..app0_count] {
+        stripped.extend_from_slice(segment);
+    }
+    if let Some(kept) = exif.kept_exif() {
+        stripped.extend_from_slice(&[0xff, 0xe1]);
+        stripped.extend_from_slice(&((kept.len() + EXIF_HEADER.len() + 2) as u16).to_be_bytes());
+        stripped.extend_from_slice(EXIF_HEADER);
+        stripped.extend_from_slice(&kept);
+    }
+    for segment in &segments[app0_count..] {
+        stripped.extend_from_slice(segment);
+    }
+
+    Some((stripped, exif))
+}
+
+/// Where the entropy-coded data after a scan header ends, at the next marker.
+fn find_jpeg_scan_end(bytes: &[u8], from: usize) -> Option<usize> {
+    let mut pos = from;
+
+    while pos + 1 < bytes.len() {
+        if bytes[pos] == 0xff && !matches!(bytes[pos + 1], 0x00 | 0xd0..=0xd7 | 0xff) {
+            return Some(pos);
+        }
+        pos += 1;
+    }
+
+    None
+}
+
+fn strip_png(bytes: &[u8]) -> Option<(Vec<u8>, ExifSummary)> {
+    if !bytes.starts_with(&PNG_SIGNATURE) {
+        return None;
+    }
+
+    let mut exif = ExifSummary::default();
+    let mut chunks: Vec<&[u8]> = vec![];
+    let mut pos = PNG_SIGNATURE.len();
+
+    loop {
+        let end = (read_u32_be(bytes, pos)? as usize).checked_add(pos + 12)?;
+        let chunk = bytes.get(pos..end)?;
+        let chunk_type = &chunk[4..8];
+
+        if chunk_type == b"eXIf" {
+            exif.merge(read_exif(&chunk[8..chunk.len() - 4]));
+        }
+        if !PNG_METADATA_CHUNKS.iter().any(|x| &x[..] == chunk_type) {
+            chunks.push(chunk);
+        }
+        pos = end;
+
+        if chunk_type == b"IEND" {
+            break;
+        }
+    }
+
+    let mut stripped = PNG_SIGNATURE.to_vec();
+    let mut kept = exif.kept_exif();
+    for chunk in chunks {
+        // note: eXIf has to come before the image data.
+        if &chunk[4..8] == b"IDAT" {
+            if let Some(kept) = kept.take() {
+                stripped.extend_from_slice(&png_chunk(b"eXIf", &kept));
+            }
+        }
+        stripped.extend_from_slice(chunk);
+    }
+
+    Some((stripped, exif))
+}
+
+fn png_chunk(chunk_type: &[u8; 4], data: &[u8]) -> Vec<u8> {
+    let mut chunk = (data.len() as u32).to_be_bytes().to_vec();
+    chunk.extend_from_slice(chunk_type);
+    chunk.extend_from_slice(data);
+    chunk.extend_from_slice(&crc32(&chunk[4..]).to_be_bytes());
+
+    chunk
+}
+
+fn crc32(bytes: &[u8]) -> u32 {
+    let mut crc = 0xffff_ffffu32;
+    for byte in bytes {
+        crc ^= *byte as u32;
+        for _ in 0..8 {
+            crc = match crc & 1 {
+                1 => (crc >> 1) ^ 0xedb8_8320,
+                _ => crc >> 1,
+            };
+        }
+    }
+
+    !crc
+}
+
+fn strip_webp(bytes: &[u8]) -> Option<(Vec<u8>, ExifSummary)> {
+    if bytes.len() < 12 || !bytes.starts_with(b"RIFF") || &bytes[8..12] != b"WEBP" {
+        return None;
+    }
+
+    let riff_end = (read_u32_le(bytes, 4)? as usize)
+        .checked_add(8)?
+        .min(bytes.len());
+    let mut exif = ExifSummary::default();
+    let mut chunks: Vec<&[u8]> = vec![];
+    let mut pos = 12;
+
+    while pos + 8 <= riff_end {
+        let size = read_u32_le(bytes, pos + 4)? as usize;
+        let end = size.checked_add(pos + 8 + size % 2)?.min(riff_end);
+        let chunk = bytes.get(pos..end)?;
+        let data = chunk.get(8..8 + size)?;
+
+        match &chunk[..4] {
+            b"EXIF" => exif.merge(read_exif(data.strip_prefix(EXIF_HEADER).unwrap_or(data))),
+            b"XMP " => {}
+            _ => chunks.push(chunk),
+        }
+        pos = end;
+    }
+
+    let kept = exif.kept_exif();
+    let has_extended_header = matches!(chunks.first(), Some(x) if x.starts_with(b"VP8X"));
+    let mut stripped = b"RIFF\0\0\0\0WEBP".to_vec();
+    for chunk in chunks {
+        let at = stripped.len();
+        stripped.extend_from_slice(chunk);
+        if chunk.starts_with(b"VP8X") {
+            let flags = stripped.get_mut(at + 8)?;
+            *flags &= !(WEBP_EXIF_FLAG | WEBP_XMP_FLAG);
+            if kept.is_some() {
+                *flags |= WEBP_EXIF_FLAG;
+            }
+        }
+    }
+    // note: only the extended format may have EXIF, which goes after the image data.
+    if let Some(kept) = kept.filter(|_| has_extended_header) {
+        stripped.extend_from_slice(b"EXIF");
+        stripped.extend_from_slice(&(kept.len() as u32).to_le_bytes());
+        stripped.extend_from_slice(&kept);
+        if kept.len() % 2 == 1 {
+            stripped.push(0);
+        }
+    }
+    let riff_size = (stripped.len() - 8) as u32;
+    stripped[4..8].copy_from_slice(&riff_size.to_le_bytes());
+
+    Some((stripped, exif))
+}
+
+/// EXIF with only the orientation, in big-endian TIFF.
+fn orientation_exif(orientation: u16) -> Vec<u8> {
+    let mut tiff = b"MM\0\x2a".to_vec();
+    tiff.extend_from_slice(&8u32.to_be_bytes());
+    tiff.extend_from_slice(&1u16.to_be_bytes());
+    tiff.extend_from_slice(&TAG_ORIENTATION.to_be_bytes());
+    // note: one SHORT, padded to the four bytes of the value.
+    tiff.extend_from_slice(&3u16.to_be_bytes());
+    tiff.extend_from_slice(&1u32.to_be_bytes());
+    tiff.extend_from_slice(&orientation.to_be_bytes());
+    tiff.extend_from_slice(&[0, 0]);
+    tiff.extend_from_slice(&0u32.to_be_bytes());
+
+    tiff
+}
+
+/// Reads the entries of the TIFF structure EXIF is stored in.
+struct Tiff<'a> {
+    bytes: &'a [u8],
+    is_little_endian: bool,
+}
+
+impl<'a> Tiff<'a> {
+    fn new(bytes: &'a [u8]) -> Option<Self> {
+        let is_little_endian = match bytes.get(..4)? {
+            b"II\x2a\0" => true,
+            b"MM\0\x2a" => false,
+            _ => return None,
+        };
+
+        Some(Self {
+            bytes,
+            is_little_endian,
+        })
+    }
+
+    fn u16(&self, at: usize) -> Option<u16> {
+        let bytes = self.bytes.get(at..at.checked_add(2)?)?.try_into().ok()?;
+
+        Some(match self.is_little_endian {
+            true => u16::from_le_bytes(bytes),
+            false => u16::from_be_bytes(bytes),
+        })
+    }
+
+    fn u32(&self, at: usize) -> Option<u32> {
+        let bytes = self.bytes.get(at..at.checked_add(4)?)?.try_into().ok()?;
+
+        Some(match self.is_little_endian {
+            true => u32::from_le_bytes(bytes),
+            false => u32::from_be_bytes(bytes),
+        })
+    }
+
+    /// Where the value of the entry of `tag` in the IFD at `ifd` is, and how many there are.
+    fn find(&self, ifd: usize, tag: u16) -> Option<(usize, usize)> {
+        let count = self.u16(ifd)? as usize;
+
+        // note: offsets come from the file, so they may point anywhere.
+        (0..count).find_map(|i| {
+            let entry = ifd.checked_add(2)?.checked_add(i * 12)?;
+            if self.u16(entry)? != tag {
+                return None;
+            }
+            let value_count = self.u32(entry.checked_add(4)?)? as usize;
+            let size = match self.u16(entry.checked_add(2)?)? {
+                3 => 2usize,
+                4 => 4,
+                _ => 1,
+            }
+            .checked_mul(value_count)?;
+
+            match size <= 4 {
+                true => Some((entry.checked_add(8)?, value_count)),
+                false => Some((self.u32(entry.checked_add(8)?)? as usize, value_count)),
+            }
+        })
+    }
+
+    fn short(&self, ifd: usize, tag: u16) -> Option<u16> {
+        let (at, _) = self.find(ifd, tag)?;
+
+        self.u16(at)
+    }
+
+    fn long(&self, ifd: usize, tag: u16) -> Option<u32> {
+        let (at, _) = self.find(ifd, tag)?;
+
+        self.u32(at)
+    }
+
+    fn ascii(&self, ifd: usize, tag: u16) -> Option<String> {
+        let (at, count) = self.find(ifd, tag)?;
+        let value = self.bytes.get(at..at.checked_add(count)?)?;
+
+        String::from_utf8(value.split(|x| *x == 0).next()?.to_vec()).ok()
+    }
+}
+
+fn read_exif(tiff: &[u8]) -> ExifSummary {
+    let tiff = match Tiff::new(tiff) {
+        Some(tiff) => tiff,
+        None => return ExifSummary::default(),
+    };
+    let ifd0 = match tiff.u32(4) {
+        Some(x) => x as usize,
+        None => return ExifSummary::default(),
+    };
+    let exif_ifd = tiff.long(ifd0, TAG_EXIF_IFD).map(|x| x as usize);
+
+    let original = exif_ifd.and_then(|ifd| {
+        let date_time = tiff.ascii(ifd, TAG_DATE_TIME_ORIGINAL)?;
+        let offset = tiff.ascii(ifd, TAG_OFFSET_TIME_ORIGINAL);
+        parse_exif_date_time(&date_time, offset.as_deref())
+    });
+    let captured_at = original.or_else(|| {
+        tiff.ascii(ifd0, TAG_DATE_TIME)
+            .and_then(|x| parse_exif_date_time(&x, None))
+    });
+
+    ExifSummary {
+        orientation: tiff.short(ifd0, TAG_ORIENTATION),
+        captured_at,
+    }
+}
+
+/// Parses `YYYY:MM:DD HH:MM:SS` of EXIF with the offset like `+09:00` recorded along, if any.
+fn parse_exif_date_time(date_time: &str, offset: Option<&str>) -> Option<i64> {
+    match offset {
+        Some(offset) => DateTime::parse_from_str(
+            &format!("{} {}", date_time.trim(), offset.trim()),
+            "%Y:%m:%d %H:%M:%S %:z",
+        )
+        .ok()
+        .map(|x| x.timestamp()),
+        None => NaiveDateTime::parse_from_str(date_time.trim(), "%Y:%m:%d %H:%M:%S")
+            .ok()
+            .map(|x| x.timestamp()),
+    }
+}
+
+#[cfg(test)]
+mod image_metadata_tests {
+    use super::*;
+
+    const GPS_LATITUDE: &[u8] = b"37.5665N";
+    const DEVICE: &[u8] = b"Phone 13 Pro";
+
+    /// Little-endian EXIF with an orientation, the make of the device, a GPS IFD and the capture
+    /// time in the EXIF IFD.
+    fn exif(orientation: u16) -> Vec<u8> {
+        let mut tiff = b"II\x2a\0".to_vec();
+        tiff.extend_from_slice(&8u32.to_le_bytes());
+
+        let entry = |tiff: &mut Vec<u8>, tag: u16, kind: u16, count: u32, value: u32| {
+            tiff.extend_from_slice(&tag.to_le_bytes());
+            tiff.extend_from_slice(&kind.to_le_bytes());
+            tiff.extend_from_slice(&count.to_le_bytes());
+            tiff.extend_from_slice(&value.to_le_bytes());
+        };
+        // note: IFD0 at 8 with 4 entries ends at 62, the EXIF IFD with 2 entries at 92.
+        let exif_ifd = 62u32;
+        let values = 92u32;
+        tiff.extend_from_slice(&4u16.to_le_bytes());
+        entry(&mut tiff, 0x010f, 2, DEVICE.len() as u32, values);
+        entry(&mut tiff, TAG_ORIENTATION, 3, 1, orientation as u32);
+        entry(&mut tiff, TAG_EXIF_IFD, 4, 1, exif_ifd);
+        entry(&mut tiff, 0x8825, 4, 1, values + 12);
+        tiff.extend_from_slice(&0u32.to_le_bytes());
+        tiff.extend_from_slice(&2u16.to_le_bytes());
+        entry(&mut tiff, TAG_DATE_TIME_ORIGINAL, 2, 20, values + 20);
+        entry(&mut tiff, TAG_OFFSET_TIME_ORIGINAL, 2, 7, values + 40);
+        tiff.extend_from_slice(&0u32.to_le_bytes());
+
+        tiff.extend_from_slice(DEVICE);
+        tiff.extend_from_slice(GPS_LATITUDE);
+        tiff.extend_from_slice(b"2022:03:01 12:00:00\0");
+        tiff.extend_from_slice(b"+09:00\0");
+
+        tiff
+    }
+
+    fn contains(bytes: &[u8], part: &[u8]) -> bool {
+        bytes.windows(part.len()).any(|x| x == part)
+    }
+
+    fn jpeg_segment(marker: u8, data: &[u8]) -> Vec<u8> {
+        let mut segment = vec![0xff, marker];
+        segment.extend_from_slice(&((data.len() + 2) as u16).to_be_bytes());
+        segment.extend_from_slice(data);
+
+        segment
+    }
+
+    fn jpeg(orientation: u16) -> Vec<u8> {
+        let mut jpeg = vec![0xff, 0xd8];
+        jpeg.extend(jpeg_segment(0xe0, b"JFIF\0\x01\x01\0\0\x01\0\x01\0\0"));
+        jpeg.extend(jpeg_segment(
+            0xe1,
+            &[EXIF_HEADER, &exif(orientation)].concat(),
+        ));
+        jpeg.extend(jpeg_segment(
+            0xe1,
+            b"http://ns.adobe.com/xap/1.0/\0<x:xmpmeta/>",
+        ));
+        jpeg.extend(jpeg_segment(0xfe, DEVICE));
+        jpeg.extend(jpeg_segment(0xdb, &[0; 65]));
+        jpeg.extend(jpeg_segment(0xda, &[1, 1, 0, 0, 63, 0]));
+        // note: entropy-coded data with a stuffed 0xff and a restart marker.
+        jpeg.extend_from_slice(&[0x12, 0xff, 0x00, 0x34, 0xff, 0xd0, 0x56]);
+        jpeg.extend_from_slice(&[0xff, 0xd9]);
+        // note: an MPF preview after the end, with its own EXIF.
+        jpeg.extend_from_slice(&[0xff, 0xd8]);
+        jpeg.extend(jpeg_segment(0xe1, &[EXIF_HEADER, &exif(1)].concat()));
+
+        jpeg
+    }
+
+    fn png(orientation: u16) -> Vec<u8> {
+        let mut png = PNG_SIGNATURE.to_vec();
+        png.extend(png_chunk(b"IHDR", &[0, 0, 0, 1, 0, 0, 0, 1, 8, 2, 0, 0, 0]));
+        png.extend(png_chunk(b"tEXt", &[b"Software\0", DEVICE].concat()));
+        png.extend(png_chunk(b"eXIf", &exif(orientation)));
+        png.extend(png_chunk(
+            b"IDAT",
+            &[0x78, 0x9c, 0x63, 0x60, 0, 0, 0, 4, 0, 1],
+        ));
+        png.extend(png_chunk(b"IEND", &[]));
+
+        png
+    }
+
+    fn webp_chunk(fourcc: &[u8; 4], data: &[u8]) -> Vec<u8> {
+        let mut chunk = fourcc.to_vec();
+        chunk.extend_from_slice(&(data.len() as u32).to_le_bytes());
+        chunk.extend_from_slice(data);
+        if data.len() % 2 == 1 {
+            chunk.push(0);
+        }
+
+        chunk
+    }
+
+    fn webp(orientation: u16) -> Vec<u8> {
+        let mut chunks = webp_chunk(
+            b"VP8X",
+            &[WEBP_EXIF_FLAG | WEBP_XMP_FLAG, 0, 0, 0, 0, 0, 0, 0, 0, 0],
+        );
+        chunks.extend(webp_chunk(b"VP8L", &[0x2f, 0, 0, 0, 0]));
+        chunks.extend(webp_chunk(b"EXIF", &exif(orientation)));
+        chunks.extend(webp_chunk(b"XMP ", b"<x:xmpmeta/>"));
+
+        let mut webp = b"RIFF".to_vec();
+        webp.extend_from_slice(&((chunks.len() + 4) as u32).to_le_bytes());
+        webp.extend_from_slice(b"WEBP");
+        webp.extend(chunks);
+
+        webp
+    }
+
+    fn assert_stripped(image: &CleanImage) {
+        assert!(!contains(&image.bytes, GPS_LATITUDE));
+        assert!(!contains(&image.bytes, DEVICE));
+        assert!(!contains(&image.bytes, b"xmpmeta"));
+        // note: 2022-03-01 12:00:00 in UTC+9.
+        assert_eq!(image.captured_at, Some(1646103600));
+    }
+
+    #[test]
+    fn should_strip_metadata_of_jpeg() {
+        let image = strip_metadata(ImageKind::Jpeg, &jpeg(1)).unwrap();
+
+        assert_stripped(&image);
+        assert!(!contains(&image.bytes, EXIF_HEADER));
+        assert!(image.bytes.starts_with(&[0xff, 0xd8, 0xff, 0xe0]));
+        assert!(image
+            .bytes
+            .ends_with(&[0x12, 0xff, 0x00, 0x34, 0xff, 0xd0, 0x56, 0xff, 0xd9]));
+    }
+
+    #[test]
+    fn should_keep_orientation_of_jpeg() {
+        let image = strip_metadata(ImageKind::Jpeg, &jpeg(6)).unwrap();
+
+        assert_stripped(&image);
+        let exif = jpeg_segment(0xe1, &[EXIF_HEADER, &orientation_exif(6)].concat());
+        // note: right after the JFIF segment.
+        assert_eq!(&image.bytes[20..20 + exif.len()], &exif[..]);
+        assert_eq!(read_exif(&orientation_exif(6)).orientation, Some(6));
+    }
+
+    #[test]
+    fn should_strip_metadata_of_png() {
+        let image = strip_metadata(ImageKind::Png, &png(1)).unwrap();
+
+        assert_stripped(&image);
+        assert!(!contains(&image.bytes, b"eXIf"));
+        assert!(image.bytes.ends_with(&png_chunk(b"IEND", &[])));
+        // note: the well-known CRC of IEND.
+        assert!(image.bytes.ends_with(&[0xae, 0x42, 0x60, 0x82]));
+    }
+
+    #[test]
+    fn should_keep_orientation_of_png_before_image_data() {
+        let image = strip_metadata(ImageKind::Png, &png(8)).unwrap();
+
+        let exif = png_chunk(b"eXIf", &orientation_exif(8));
+        let at = image.bytes.windows(4).position(|x| x == b"IDAT").unwrap() - 4;
+        assert_eq!(&image.bytes[at - exif.len()..at], &exif[..]);
+    }
+
+    #[test]
+    fn should_strip_metadata_of_webp() {
+        let image = strip_metadata(ImageKind::Webp, &webp(1)).unwrap();
+
+        assert_stripped(&image);
+        assert!(!contains(&image.bytes, b"EXIF"));
+        assert_eq!(image.bytes[20] & (WEBP_EXIF_FLAG | WEBP_XMP_FLAG), 0);
+        assert_eq!(
+            read_u32_le(&image.bytes, 4).unwrap() as usize,
+            image.bytes.len() - 8
+        );
+    }
+
+    #[test]
+    fn should_keep_orientation_of_webp() {
+        let image = strip_metadata(ImageKind::Webp, &webp(3)).unwrap();
+
+        assert_eq!(image.bytes[20] & WEBP_EXIF_FLAG, WEBP_EXIF_FLAG);
+        assert!(image
+            .bytes
+            .ends_with(&webp_chunk(b"EXIF", &orientation_exif(3))));
+    }
+
+    #[test]
+    fn should_not_strip_truncated_image() {
+        let jpeg = jpeg(1);
+
+        assert!(strip_metadata(ImageKind::Jpeg, &jpeg[..30]).is_err());
+        assert!(strip_metadata(ImageKind::Png, &PNG_SIGNATURE).is_err());
+    }
+
+    #[test]
+    fn should_not_read_past_offsets_out_of_range() {
+        let exif = exif(1);
+        let tiff = Tiff::new(&exif).unwrap();
+
+        assert_eq!(tiff.find(usize::MAX - 1, TAG_ORIENTATION), None);
+        assert_eq!(tiff.u32(usize::MAX - 2), None);
+
+        let mut webp = webp(1);
+        webp[4..8].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert!(strip_metadata(ImageKind::Webp, &webp).is_ok());
+    }
+
+    #[test]
+    fn should_parse_exif_date_time() {
+        assert_eq!(
+            parse_exif_date_time("2022:03:01 12:00:00", Some("+09:00")),
+            Some(1646103600)
+        );
+        assert_eq!(
+            parse_exif_date_time("2022:03:01 03:00:00", None),
+            Some(1646103600)
+        );
+        assert_eq!(parse_exif_date_time("0000:00:00 00:00:00", None), None);
+    }
+}
@@ -0,0 +1,525 @@
+use std::collections::HashSet;
+
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use worker::js_sys::{Array, Function, Object, Promise, Reflect, Uint8Array};
+use worker::wasm_bindgen::{JsCast, JsValue};
+use worker::wasm_bindgen_futures::JsFuture;
+use worker::{Env, Headers, Request, Response, RouteContext};
+
+use crate::api_error::ApiError;
+use crate::api_result::ApiResult;
+use crate::image_metadata::strip_metadata;
+use crate::uid;
+
+pub const IMAGES_BUCKET: &str = "IMAGES";
+pub const MAX_IMAGE_BYTES: usize = 5 * 1024 * 1024;
+pub const MAX_IMAGES_PER_FOODNOTE: usize = 10;
+/// Uploaded images never change under their key, so they may be cached for good.
+pub const IMAGE_CACHE_MAX_AGE: i32 = 60 * 60 * 24 * 365;
+
+const IMAGE_PREFIX: &str = "images/";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ImageKind {
+    Jpeg,
+    Png,
+    Webp,
+}
+
+impl ImageKind {
+    /// Tells the kind from the magic bytes, whatever the client claims the content type is.
+    pub fn sniff(bytes: &[u8]) -> Option<Self> {
+        if bytes.starts_with(&[0xff, 0xd8, 0xff]) {
+            return Some(ImageKind::Jpeg);
+        }
+        if bytes.starts_with(&[0x89, b'P', b'N', b'G', 0x0d, 0x0a, 0x1a, 0x0a]) {
+            return Some(ImageKind::Png);
+        }
+        if bytes.len() >= 12 && bytes.starts_with(b"RIFF") && &bytes[8..12] == b"WEBP" {
+            return Some(ImageKind::Webp);
+        }
+
+        None
+    }
+
+    pub fn from_extension(extension: &str) -> Option<Self> {
+        match extension {
+            "jpg" => Some(ImageKind::Jpeg),
+            "png" => Some(ImageKind::Png),
+            "webp" => Some(ImageKind::Webp),
+            _ => None,
+        }
+    }
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            ImageKind::Jpeg => "jpg",
+            ImageKind::Png => "png",
+            ImageKind::Webp => "webp",
+        }
+    }
+
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            ImageKind::Jpeg => "image/jpeg",
+            ImageKind::Png => "image/png",
+            ImageKind::Webp => "image/webp",
+        }
+    }
+}
+
+pub fn user_images_prefix(user_id: &str) -> String {
+    format!("{}{}/", IMAGE_PREFIX, user_id)
+}
+
+pub fn image_key(user_id: &str, image_id: &str, kind: ImageKind) -> String {
+    format!(
+        "{}{}.{}",
+        user_images_prefix(user_id),
+        image_id,
+        kind.extension()
+    )
+}
+
+fn is_uid(value: &str) -> bool {
+    !value.is_empty() && value.chars().all(|x| x.is_ascii_alphanumeric())
+}
+
+/// The bucket key of an uploaded image from its url, absolute or only the path. `None` for
+/// images hosted anywhere else.
+pub fn image_key_of_url(url: &str) -> Option<String> {
+    let path = match url.find("://") {
+        Some(i) => {
+            let rest = &url[i + 3..];
+            &rest[rest.find('/')?..]
+        }
+        None => url,
+    };
+    let key = path.strip_prefix('/')?;
+
+    match key
+        .strip_prefix(IMAGE_PREFIX)?
+        .split('/')
+        .collect::<Vec<&str>>()[..]
+    {
+        [user_id, name] if is_uid(user_id) => {
+            let (image_id, extension) = name.split_once('.')?;
+            ImageKind::from_extension(extension)?;
+
+            match is_uid(image_id) {
+                true => Some(key.to_owned()),
+                false => None,
+            }
+        }
+        _ => None,
+    }
+}
+
+/// Images of a foodnote have to be uploaded by its author, and not too many.
+pub fn check_img_urls(author_id: &str, img_urls: &[String]) -> ApiResult<Vec<String>> {
+    if img_urls.len() > MAX_IMAGES_PER_FOODNOTE {
+        return Err(ApiError::BadRequest(format!(
+            "at most {} images per foodnote",
+            MAX_IMAGES_PER_FOODNOTE
+        )));
+    }
+
+    let prefix = user_images_prefix(author_id);
+    img_urls
+        .iter()
+        .map(|url| match image_key_of_url(url) {
+            Some(key) if key.starts_with(&prefix) => Ok(key),
+            _ => Err(ApiError::BadRequest(format!(
+                "not an uploaded image: {}",
+                url
+            ))),
+        })
+        .collect()
+}
+
+/// Keys of `removed` images that none of the `kept` urls refer to anymore.
+pub fn unreferenced_image_keys(removed: &[String], kept: &[String]) -> Vec<String> {
+    let kept = kept
+        .iter()
+        .filter_map(|x| image_key_of_url(x))
+        .collect::<HashSet<String>>();
+    let mut keys = removed
+        .iter()
+        .filter_map(|x| image_key_of_url(x))
+        .filter(|x| !kept.contains(x))
+        .collect::<Vec<String>>();
+    keys.sort();
+    keys.dedup();
+
+    keys
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct StoredImage {
+    pub bytes: Vec<u8>,
+    pub content_type: String,
+    /// When the photo was taken, read from its metadata before it was stripped.
+    pub captured_at: Option<i64>,
+}
+
+#[async_trait(? Send)]
+pub trait ImageBucket {
+    async fn put(&self, key: &str, image: &StoredImage) -> ApiResult<()>;
+    async fn get(&self, key: &str) -> ApiResult<Option<StoredImage>>;
+    async fn contains(&self, key: &str) -> ApiResult<bool>;
+    async fn delete(&self, key: &str) -> ApiResult<()>;
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ImageDto {
+    pub key: String,
+    pub url: String,
+    pub content_type: String,
+    pub size: usize,
+    /// Clients may prefill the visit time of the foodnote with it.
+    pub captured_at: Option<i64>,
+}
+
+/// Checks an uploaded image and stores it without its metadata under the prefix of the uploader.
+pub async fn upload_image<B: ImageBucket>(
+    bucket: &B,
+    user_id: &str,
+    bytes: Vec<u8>,
+) -> ApiResult<(String, StoredImage)> {
+    if bytes.len() > MAX_IMAGE_BYTES {
+        return Err(ApiError::ImageTooLarge);
+    }
+    let kind = ImageKind::sniff(&bytes).ok_or(ApiError::UnsupportedImage)?;
+
+    let key = image_key(user_id, &uid!(), kind);
+    let clean = strip_metadata(kind, &bytes)?;
+    let image = StoredImage {
+        bytes: clean.bytes,
+        content_type: kind.content_type().to_owned(),
+        captured_at: clean.captured_at,
+    };
+    bucket.put(&key, &image).await?;
+
+    Ok((key, image))
+}
+
+/// Uploaded images of the author a foodnote may refer to, as they are stored.
+pub async fn check_uploaded_images<B: ImageBucket>(
+    bucket: &B,
+    author_id: &str,
+    img_urls: &[String],
+) -> ApiResult<()> {
+    for key in check_img_urls(author_id, img_urls)? {
+        if !bucket.contains(&key).await? {
+            return Err(ApiError::BadRequest(format!(
+                "not an uploaded image: {}",
+                key
+            )));
+        }
+    }
+
+    Ok(())
+}
+
+pub async fn delete_images<B: ImageBucket>(bucket: &B, keys: &[String]) -> ApiResult<()> {
+    for key in keys {
+        bucket.delete(key).await?;
+    }
+
+    Ok(())
+}
+
+/// The R2 bucket bound as `IMAGES`. This version of `worker` has no R2 bindings, so it calls the
+/// JavaScript API of the binding itself.
+pub struct R2Bucket {
+    inner: JsValue,
+}
+
+impl R2Bucket {
+    pub fn new(env: &Env) -> ApiResult<Self> {
+        let inner = Reflect::get(env, &JsValue::from(IMAGES_BUCKET))
+            .map_err(|_| ApiError::ServerError("no images bucket".to_string()))?;
+        if inner.is_undefined() {
+            return Err(ApiError::ServerError("no images bucket".to_string()));
+        }
+
+        Ok(Self { inner })
+    }
+
+    async fn call(&self, method: &str, args: &[JsValue]) -> ApiResult<JsValue> {
+        let function = Reflect::get(&self.inner, &JsValue::from(method))
+            .map_err(worker::Error::from)?
+            .dyn_into::<Function>()
+            .map_err(worker::Error::from)?;
+        let promise = function
+            .apply(&self.inner, &args.iter().collect::<Array>())
+            .map_err(worker::Error::from)?
+            .dyn_into::<Promise>()
+            .map_err(worker::Error::from)?;
+
+        Ok(JsFuture::from(promise).await.map_err(worker::Error::from)?)
+    }
+}
+
+#[async_trait(? Send)]
+impl ImageBucket for R2Bucket {
+    async fn put(&self, key: &str, image: &StoredImage) -> ApiResult<()> {
+        let http_metadata = Object::new();
+        Reflect::set(
+            &http_metadata,
+            &JsValue::from("contentType"),
+            &JsValue::from(image.content_type.as_str()),
+        )
+        .map_err(worker::Error::from)?;
+        let custom_metadata = Object::new();
+        if let Some(captured_at) = image.captured_at {
+            Reflect::set(
+                &custom_metadata,
+                &JsValue::from("captured_at"),
+                &JsValue::from(captured_at.to_string()),
+            )
+            .map_err(worker::Error::from)?;
+        }
+        let options = Object::new();
+        Reflect::set(&options, &JsValue::from("httpMetadata"), &http_metadata)
+            .map_err(worker::Error::from)?;
+        Reflect::set(&options, &JsValue::from("customMetadata"), &custom_metadata)
+            .map_err(worker::Error::from)?;
+
+        let body = Uint8Array::from(image.bytes.as_slice());
+        self.call("put", &[JsValue::from(key), body.into(), options.into()])
+            .await?;
+
+        Ok(())
+    }
+
+    async fn get(&self, key: &str) -> ApiResult<Option<StoredImage>> {
+        let object = self.call("get", &[JsValue::from(key)]).await?;
+        if object.is_null() || object.is_undefined() {
+            return Ok(None);
+        }
+
+        let content_type = Reflect::get(&object, &JsValue::from("httpMetadata"))
+            .and_then(|x| Reflect::get(&x, &JsValue::from("contentType")))
+            .ok()
+            .and_then(|x| x.as_string())
+            .unwrap_or_else(|| "application/octet-stream".to_string());
+        let captured_at = Reflect::get(&object, &JsValue::from("customMetadata"))
+            .and_then(|x| Reflect::get(&x, &JsValue::from("captured_at")))
+            .ok()
+            .and_then(|x| x.as_string())
+            .and_then(|x| x.parse::<i64>().ok());
+        let array_buffer = Reflect::get(&object, &JsValue::from("arrayBuffer"))
+            .map_err(worker::Error::from)?
+            .dyn_into::<Function>()
+            .map_err(worker::Error::from)?
+            .call0(&object)
+            .map_err(worker::Error::from)?
+            .dyn_into::<Promise>()
+            .map_err(worker::Error::from)?;
+        let array_buffer = JsFuture::from(array_buffer)
+            .await
+            .map_err(worker::Error::from)?;
+
+        Ok(Some(StoredImage {
+            bytes: Uint8Array::new(&array_buffer).to_vec(),
+            content_type,
+            captured_at,
+        }))
+    }
+
+    async fn contains(&self, key: &str) -> ApiResult<bool> {
+        let object = self.call("head", &[JsValue::from(key)]).await?;
+
+        Ok(!object.is_null() && !object.is_undefined())
+    }
+
+    async fn delete(&self, key: &str) -> ApiResult<()> {
+        self.call("delete", &[JsValue::from(key)]).await?;
+
+        Ok(())
+    }
+}
+
+/// Serves an uploaded image. Anyone with the url may see it, like the images hosted elsewhere.
+pub async fn get_image(req: Request, ctx: RouteContext<()>) -> ApiResult<Response> {
+    let key = image_key_of_url(req.path().as_str()).ok_or(ApiError::ImageNotExists)?;
+    let bucket = R2Bucket::new(&ctx.env)?;
+    let image = bucket.get(&key).await?.ok_or(ApiError::ImageNotExists)?;
+
+    let mut headers = Headers::new();
+    headers.set("content-type", &image.content_type)?;
+    headers.append(
+        "cache-control",
+        &format!("public, max-age={}, immutable", IMAGE_CACHE_MAX_AGE),
+    )?;
+
+    Ok(Response::from_bytes(image.bytes)?.with_headers(headers))
+}
+
+#[cfg(test)]
+mod images_tests {
+    use std::cell::RefCell;
+    use std::collections::HashMap;
+    use std::future::Future;
+    use std::pin::Pin;
+    use std::task::{Context, Poll, RawWaker, RawWakerVTable, Waker};
+
+    use super::*;
+
+    /// The smallest JPEG stripping gets through, with an empty scan.
+    const JPEG: [u8; 15] = [
+        0xff, 0xd8, 0xff, 0xe0, 0x00, 0x04, b'J', b'F', 0xff, 0xda, 0x00, 0x02, 0x00, 0xff, 0xd9,
+    ];
+
+    /// Stands in for R2 with a map, so uploads are checked without a bucket.
+    #[derive(Default)]
+    struct MemoryBucket {
+        images: RefCell<HashMap<String, StoredImage>>,
+    }
+
+    #[async_trait(? Send)]
+    impl ImageBucket for MemoryBucket {
+        async fn put(&self, key: &str, image: &StoredImage) -> ApiResult<()> {
+            self.images
+                .borrow_mut()
+                .insert(key.to_owned(), image.clone());
+            Ok(())
+        }
+
+        async fn get(&self, key: &str) -> ApiResult<Option<StoredImage>> {
+            Ok(self.images.borrow().get(key).cloned())
+        }
+
+        async fn contains(&self, key: &str) -> ApiResult<bool> {
+            Ok(self.images.borrow().contains_key(key))
+        }
+
+        async fn delete(&self, key: &str) -> ApiResult<()> {
+            self.images.borrow_mut().remove(key);
+            Ok(())
+        }
+    }
+
+    /// Runs a future of the memory bucket, which never has to wait.
+    fn block_on<F: Future>(future: F) -> F::Output {
+        fn raw_waker() -> RawWaker {
+            fn clone(_: *const ()) -> RawWaker {
+                raw_waker()
+            }
+            fn noop(_: *const ()) {}
+            static VTABLE: RawWakerVTable = RawWakerVTable::new(clone, noop, noop, noop);
+
+            RawWaker::new(std::ptr::null(), &VTABLE)
+        }
+
+        let waker = unsafe { Waker::from_raw(raw_waker()) };
+        let mut context = Context::from_waker(&waker);
+        let mut future = Box::pin(future);
+
+        match Pin::new(&mut future).poll(&mut context) {
+            Poll::Ready(output) => output,
+            Poll::Pending => panic!("memory bucket should not wait"),
+        }
+    }
+
+    #[test]
+    fn should_sniff_image_kind_by_magic_bytes() {
+        assert_eq!(ImageKind::sniff(&JPEG), Some(ImageKind::Jpeg));
+        assert_eq!(
+            ImageKind::sniff(b"\x89PNG\r\n\x1a\n...."),
+            Some(ImageKind::Png)
+        );
+        assert_eq!(
+            ImageKind::sniff(b"RIFF\x10\x00\x00\x00WEBPVP8 "),
+            Some(ImageKind::Webp)
+        );
+        assert_eq!(ImageKind::sniff(b"RIFF\x10\x00\x00\x00WAVE"), None);
+        assert_eq!(ImageKind::sniff(b"<svg></svg>"), None);
+    }
+
+    #[test]
+    fn should_find_image_key_of_url() {
+        assert_eq!(
+            image_key_of_url("https://api.foodrhapsody.com/images/alice/abc.jpg").as_deref(),
+            Some("images/alice/abc.jpg")
+        );
+        assert_eq!(
+            image_key_of_url("/images/alice/abc.png").as_deref(),
+            Some("images/alice/abc.png")
+        );
+        assert_eq!(image_key_of_url("https://example.com/cat.jpg"), None);
+        assert_eq!(image_key_of_url("/images/alice/../bob/abc.jpg"), None);
+        assert_eq!(image_key_of_url("/images/alice/abc.gif"), None);
+    }
+
+    #[test]
+    fn should_check_img_urls_of_author() {
+        let own = vec!["/images/alice/abc.jpg".to_string()];
+        assert_eq!(
+            check_img_urls("alice", &own).unwrap(),
+            vec!["images/alice/abc.jpg"]
+        );
+        assert!(check_img_urls("bob", &own).is_err());
+        assert!(check_img_urls("alice", &["https://example.com/cat.jpg".to_string()]).is_err());
+
+        let too_many = vec!["/images/alice/abc.jpg".to_string(); MAX_IMAGES_PER_FOODNOTE + 1];
+        assert!(check_img_urls("alice", &too_many).is_err());
+    }
+
+    #[test]
+    fn should_upload_image_under_user_prefix() {
+        let bucket = MemoryBucket::default();
+        let (key, image) = block_on(upload_image(&bucket, "alice", JPEG.to_vec())).unwrap();
+
+        assert!(key.starts_with("images/alice/"));
+        assert!(key.ends_with(".jpg"));
+        assert_eq!(image.content_type, "image/jpeg");
+        assert_eq!(image.captured_at, None);
+        assert_eq!(block_on(bucket.get(&key)).unwrap(), Some(image));
+        assert!(block_on(check_uploaded_images(
+            &bucket,
+            "alice",
+            &[format!("/{}", key)]
+        ))
+        .is_ok());
+        assert!(block_on(check_uploaded_images(
+            &bucket,
+            "alice",
+            &["/images/alice/missing.jpg".to_string()]
+        ))
+        .is_err());
+    }
+
+    #[test]
+    fn should_not_upload_invalid_image() {
+        let bucket = MemoryBucket::default();
+
+        let not_image = block_on(upload_image(&bucket, "alice", b"<svg></svg>".to_vec()));
+        assert!(matches!(not_image, Err(ApiError::UnsupportedImage)));
+
+        let mut large = JPEG.to_vec();
+        large.resize(MAX_IMAGE_BYTES + 1, 0);
+        let too_large = block_on(upload_image(&bucket, "alice", large));
+        assert!(matches!(too_large, Err(ApiError::ImageTooLarge)));
+
+        assert!(bucket.images.borrow().is_empty());
+    }
+
+    #[test]
+    fn should_collect_only_unreferenced_images() {
+        let bucket = MemoryBucket::default();
+        let (a, _) = block_on(upload_image(&bucket, "alice", JPEG.to_vec())).unwrap();
+        let (b, _) = block_on(upload_image(&bucket, "alice", JPEG.to_vec())).unwrap();
+
+        let removed = vec![format!("/{}", a), format!("https://host/{}", b)];
+        let kept = vec![format!("/{}", b)];
+        let keys = unreferenced_image_keys(&removed, &kept);
+        assert_eq!(keys, vec![a.clone()]);
+
+        block_on(delete_images(&bucket, &keys)).unwrap();
+        assert!(!block_on(bucket.contains(&a)).unwrap());
+        assert!(block_on(bucket.contains(&b)).unwrap());
+    }
+}
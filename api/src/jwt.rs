@@ -43,6 +43,19 @@ impl Jwt {
     }
 
     pub fn verify<T: DeserializeOwned>(&self, token_str: &str) -> Result<Token<T>, JwtError> {
+        let token = self.verify_integrity::<T>(token_str)?;
+
+        match token.claims().validate_expiration(&TimeOptions::default()) {
+            Ok(_) => Ok(token),
+            Err(e) => Err(JwtError::ValidationError(e)),
+        }
+    }
+
+    /// Verifies only the signature of a token, so an expired token is still read.
+    pub fn verify_integrity<T: DeserializeOwned>(
+        &self,
+        token_str: &str,
+    ) -> Result<Token<T>, JwtError> {
         let verifying_key = Hs256Key::new(&self.secret);
         let parsed_token = UntrustedToken::new(&token_str);
         if let Err(e) = parsed_token {
@@ -50,14 +63,8 @@ impl Jwt {
         }
 
         let parsed_token = parsed_token.unwrap();
-        let token = Hs256.validate_integrity::<T>(&parsed_token, &verifying_key);
-        if let Err(e) = token {
-            return Err(JwtError::ValidationError(e));
-        }
-
-        let token = token.unwrap();
-        match token.claims().validate_expiration(&TimeOptions::default()) {
-            Ok(_) => Ok(token),
+        match Hs256.validate_integrity::<T>(&parsed_token, &verifying_key) {
+            Ok(token) => Ok(token),
             Err(e) => Err(JwtError::ValidationError(e)),
         }
     }
@@ -121,4 +128,21 @@ mod jwt_tests {
             JwtError::ValidationError(ValidationError::Expired)
         ));
     }
+
+    #[test]
+    fn should_verify_integrity_of_expired_token() {
+        let jwt = Jwt::new("this_is_secret");
+        let custom = CustomClaims {
+            subject: "alice".to_owned(),
+        };
+
+        let claims = jwt.create_claims(custom, Duration::hours(-1));
+        let token = jwt.sign(&claims).unwrap();
+        let verified = jwt.verify_integrity::<CustomClaims>(&token).unwrap();
+
+        assert_eq!(verified.claims().custom.subject, "alice");
+        assert!(Jwt::new("other_secret")
+            .verify_integrity::<CustomClaims>(&token)
+            .is_err());
+    }
 }
@@ -0,0 +1,263 @@
+use serde::{Deserialize, Serialize};
+
+use crate::badges::Badge;
+use crate::progress::{ChallengeProgress, CollectedStamp};
+use crate::users::UserProfileDto;
+
+pub const DEFAULT_LEADERBOARD_LIMIT: usize = 10;
+pub const MAX_LEADERBOARD_LIMIT: usize = 100;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RankEntry {
+    pub user_id: String,
+    pub collected: u32,
+    /// Seconds from the first collected stamp to completing the challenge(s).
+    pub completed_in: Option<i64>,
+    /// When `collected` was last reached, used to break ties in favor of the earlier one.
+    pub reached_at: i64,
+}
+
+impl RankEntry {
+    /// Ranks an author in one challenge; `None` before any stamp of it is collected.
+    pub fn for_challenge(
+        user_id: &str,
+        progress: &ChallengeProgress,
+        badge: Option<&Badge>,
+    ) -> Option<Self> {
+        let first_collected_at = progress.first_collected_at?;
+        let reached_at = progress
+            .collected
+            .iter()
+            .map(|x| x.first_collected_at)
+            .max()
+            .unwrap_or(first_collected_at);
+
+        Some(Self {
+            user_id: user_id.to_owned(),
+            collected: progress.collected.len() as u32,
+            completed_in: badge.map(|x| (x.completed_at - first_collected_at).max(0)),
+            reached_at,
+        })
+    }
+
+    /// Ranks an author across every stamp; `None` before any stamp is collected.
+    pub fn global(user_id: &str, collected: &[CollectedStamp], badges: &[Badge]) -> Option<Self> {
+        let first_collected_at = collected.iter().map(|x| x.first_collected_at).min()?;
+        let reached_at = collected
+            .iter()
+            .map(|x| x.first_collected_at)
+            .max()
+            .unwrap_or(first_collected_at);
+        let completed_in = badges
+            .iter()
+            .map(|x| x.completed_at)
+            .max()
+            .map(|x| (x - first_collected_at).max(0));
+
+        Some(Self {
+            user_id: user_id.to_owned(),
+            collected: collected.len() as u32,
+            completed_in,
+            reached_at,
+        })
+    }
+
+    /// A key sorting entries in rank order: most stamps first, then the fastest completion, then
+    /// whoever got there first.
+    pub fn sort_key(&self) -> String {
+        // note: numbers are zero-padded to compare as text, and flipping the sign bit of
+        // `reached_at` keeps negative timestamps in order.
+        let completed_in = match self.completed_in {
+            Some(x) => format!("0{:019}", x.max(0)),
+            None => format!("1{:019}", 0),
+        };
+
+        format!(
+            "{:010}_{}_{:020}_{}",
+            u32::MAX - self.collected,
+            completed_in,
+            (self.reached_at as u64) ^ (1 << 63),
+            self.user_id
+        )
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RankedEntryDto {
+    pub rank: u32,
+    #[serde(flatten)]
+    pub entry: RankEntry,
+    pub name: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Leaderboard {
+    pub top: Vec<RankedEntryDto>,
+    pub me: Option<RankedEntryDto>,
+    pub opted_out: bool,
+}
+
+impl Leaderboard {
+    /// Ranks `top`, the first entries in rank order, next to the entry of the user with its rank.
+    pub fn new(top: Vec<RankEntry>, me: Option<(u32, RankEntry)>, opted_out: bool) -> Self {
+        let top = top
+            .into_iter()
+            .enumerate()
+            .map(|(index, entry)| RankedEntryDto {
+                rank: index as u32 + 1,
+                entry,
+                name: None,
+            })
+            .collect();
+        let me = me.map(|(rank, entry)| RankedEntryDto {
+            rank,
+            entry,
+            name: None,
+        });
+
+        Self { top, me, opted_out }
+    }
+
+    pub fn with_profiles(mut self, profiles: &[UserProfileDto]) -> Self {
+        let name_of = |user_id: &str| {
+            profiles
+                .iter()
+                .find(|x| x.id == user_id)
+                .and_then(|x| x.name.clone())
+        };
+
+        for x in self.top.iter_mut().chain(self.me.iter_mut()) {
+            x.name = name_of(&x.entry.user_id);
+        }
+
+        self
+    }
+}
+
+pub fn parse_leaderboard_limit(value: Option<&str>) -> usize {
+    value
+        .and_then(|x| x.parse::<usize>().ok())
+        .unwrap_or(DEFAULT_LEADERBOARD_LIMIT)
+        .clamp(1, MAX_LEADERBOARD_LIMIT)
+}
+
+#[cfg(test)]
+mod leaderboard_tests {
+    use std::cmp::Ordering;
+
+    use super::*;
+    use crate::progress::collect_stamps;
+    use crate::test_fixtures::{challenge, foodnote};
+
+    fn compare_ranks(a: &RankEntry, b: &RankEntry) -> Ordering {
+        b.collected
+            .cmp(&a.collected)
+            .then_with(|| match (a.completed_in, b.completed_in) {
+                (Some(a), Some(b)) => a.cmp(&b),
+                (Some(_), None) => Ordering::Less,
+                (None, Some(_)) => Ordering::Greater,
+                (None, None) => Ordering::Equal,
+            })
+            .then_with(|| a.reached_at.cmp(&b.reached_at))
+            .then_with(|| a.user_id.cmp(&b.user_id))
+    }
+
+    fn entry(
+        user_id: &str,
+        collected: u32,
+        completed_in: Option<i64>,
+        reached_at: i64,
+    ) -> RankEntry {
+        RankEntry {
+            user_id: user_id.to_string(),
+            collected,
+            completed_in,
+            reached_at,
+        }
+    }
+
+    #[test]
+    fn should_rank_challenge_progress() {
+        let challenge = challenge(&["a", "b", "c"]);
+        let collected = collect_stamps(&[foodnote("a", 10), foodnote("b", 40)]);
+        let progress = ChallengeProgress::new(&challenge, &collected);
+        let entry = RankEntry::for_challenge("alice", &progress, None).unwrap();
+
+        assert_eq!(entry.collected, 2);
+        assert_eq!(entry.completed_in, None);
+        assert_eq!(entry.reached_at, 40);
+    }
+
+    #[test]
+    fn should_not_rank_without_collected_stamps() {
+        let progress = ChallengeProgress::new(&challenge(&["a"]), &[]);
+
+        assert!(RankEntry::for_challenge("alice", &progress, None).is_none());
+        assert!(RankEntry::global("alice", &[], &[]).is_none());
+    }
+
+    #[test]
+    fn should_sort_by_collected_then_completion_time() {
+        let mut entries = [
+            entry("slow", 3, Some(100), 10),
+            entry("few", 1, None, 1),
+            entry("fast", 3, Some(50), 20),
+            entry("unfinished", 3, None, 5),
+        ];
+        entries.sort_by_key(|x| x.sort_key());
+
+        let order = entries
+            .iter()
+            .map(|x| x.user_id.as_str())
+            .collect::<Vec<&str>>();
+        assert_eq!(order, vec!["fast", "slow", "unfinished", "few"]);
+    }
+
+    #[test]
+    fn should_sort_keys_like_ranks() {
+        let mut entries = vec![
+            entry("slow", 3, Some(100), 10),
+            entry("few", 1, None, 1),
+            entry("fast", 3, Some(50), 20),
+            entry("unfinished", 3, None, 5),
+            entry("many", 12, None, 5),
+            entry("early", 1, None, -10),
+            entry("b", 3, Some(50), 20),
+        ];
+        let mut by_key = entries.clone();
+        entries.sort_by(compare_ranks);
+        by_key.sort_by_key(|x| x.sort_key());
+
+        assert_eq!(by_key, entries);
+    }
+
+    #[test]
+    fn should_rank_top_entries_and_mine() {
+        let top = vec![entry("a", 5, None, 1), entry("b", 4, None, 1)];
+        let leaderboard = Leaderboard::new(top, Some((4, entry("me", 1, None, 1))), false);
+
+        assert_eq!(leaderboard.top.len(), 2);
+        assert_eq!(leaderboard.top[1].rank, 2);
+        assert_eq!(leaderboard.me.unwrap().rank, 4);
+    }
+
+    #[test]
+    fn should_have_no_rank_when_not_ranked() {
+        let leaderboard = Leaderboard::new(vec![entry("a", 5, None, 1)], None, true);
+
+        assert!(leaderboard.me.is_none());
+        assert!(leaderboard.opted_out);
+    }
+
+    #[test]
+    fn should_parse_leaderboard_limit() {
+        assert_eq!(parse_leaderboard_limit(None), DEFAULT_LEADERBOARD_LIMIT);
+        assert_eq!(parse_leaderboard_limit(Some("5")), 5);
+        assert_eq!(parse_leaderboard_limit(Some("0")), 1);
+        assert_eq!(parse_leaderboard_limit(Some("1000")), MAX_LEADERBOARD_LIMIT);
+        assert_eq!(
+            parse_leaderboard_limit(Some("abc")),
+            DEFAULT_LEADERBOARD_LIMIT
+        );
+    }
+}
@@ -2,26 +2,48 @@ use worker::*;
 
 use crate::api_error::ApiError;
 use crate::auth::{build_admin_auth_req, build_auth_req};
+use crate::challenges::{is_admin_view, ChallengesClient};
+use crate::identity::{
+    is_identity_header, sign_identity, Identity, Role, IDENTITY_HEADER, IDENTITY_SECRET,
+};
+use crate::images::get_image;
+use crate::jwt::Jwt;
 use crate::place::search_place;
 use crate::routes::{health_route, version_route};
 use crate::users::UserInfoDto;
 use crate::utils::wasm::set_panic_hook;
-use crate::wasm_bindgen::JsValue;
 
 mod api_error;
 mod api_result;
 mod auth;
+mod badges;
+mod bulk;
 mod challenges;
+mod claim_codes;
+mod constraints;
 mod durable;
+mod feed;
 mod foodnotes;
+mod geo;
+mod history;
+mod identity;
+mod image_metadata;
+mod images;
 mod jwt;
+mod leaderboards;
+mod locales;
 mod oauth;
+mod pagination;
 mod place;
+mod progress;
 mod req;
 mod res;
 mod routes;
+#[cfg(test)]
+mod test_fixtures;
 mod users;
 mod utils;
+mod visibility;
 
 fn get_users_stub(ctx: &RouteContext<()>) -> Result<Stub> {
     ctx.durable_object("USERS")?
@@ -41,72 +63,173 @@ fn get_foodnotes_stub(ctx: &RouteContext<()>) -> Result<Stub> {
         .get_stub()
 }
 
+async fn request_to_challenges_for_admin(req: Request, ctx: RouteContext<()>) -> Result<Response> {
+    let users_stub = get_users_stub(&ctx)?;
+    let challenges_stub = get_challenges_stub(&ctx)?;
+    let admin_auth_req = build_admin_auth_req(&req)?;
+
+    match users_stub.fetch_with_request(admin_auth_req).await {
+        Ok(mut res) => match res.status_code() {
+            200 => {
+                let admin_id = res.json::<UserInfoDto>().await?.id;
+                let roles = [Role::User, Role::Admin];
+                forward_with_identity(req, &ctx, &admin_id, &roles, challenges_stub).await
+            }
+            _ => Ok(ApiError::Unauthorized.to_response()),
+        },
+        Err(e) => Err(e),
+    }
+}
+
+/// Authenticates the request and forwards it to `stub` with an identity assertion for the user.
+async fn request_with_user(req: Request, ctx: &RouteContext<()>, stub: Stub) -> Result<Response> {
+    let users_stub = get_users_stub(ctx)?;
+    let auth_req = build_auth_req(&req)?;
+
+    match users_stub.fetch_with_request(auth_req).await {
+        Ok(mut res) => match res.status_code() {
+            200 => {
+                let user_id = res.json::<UserInfoDto>().await?.id;
+                forward_with_identity(req, ctx, &user_id, &[Role::User], stub).await
+            }
+            _ => Ok(ApiError::Unauthorized.to_response()),
+        },
+        Err(e) => Err(e),
+    }
+}
+
+/// Forwards the request to `stub` with only a short-lived signed identity assertion and the body.
+async fn forward_with_identity(
+    mut req: Request,
+    ctx: &RouteContext<()>,
+    user_id: &str,
+    roles: &[Role],
+    stub: Stub,
+) -> Result<Response> {
+    let request_id = match req.headers().get("cf-ray")? {
+        Some(ray) => ray,
+        None => uid!(),
+    };
+    let identity = Identity::new(user_id, roles, &request_id);
+    let jwt = Jwt::new(&ctx.secret(IDENTITY_SECRET)?.to_string());
+    let assertion = match sign_identity(&jwt, &identity) {
+        Ok(assertion) => assertion,
+        Err(e) => return Ok(e.to_response()),
+    };
+
+    let mut req_headers = Headers::new();
+    req_headers.append(IDENTITY_HEADER, &assertion)?;
+    if let Some(language) = req.headers().get("Accept-Language")? {
+        req_headers.append("Accept-Language", &language)?;
+    }
+
+    let mut req_init = RequestInit::new();
+    req_init.with_method(req.method()).with_headers(req_headers);
+
+    // note: bytes, as images are uploaded through here too.
+    let body = req.bytes().await?;
+    if !body.is_empty() {
+        req_init.with_body(Some(js_sys::Uint8Array::from(body.as_slice()).into()));
+    }
+
+    let user_req = Request::new_with_init(req.url()?.as_str(), &req_init)?;
+
+    stub.fetch_with_request(user_req).await
+}
+
+/// Forwards the request to `stub` as is, except for identity headers a client may have forged.
+async fn forward_without_identity(mut req: Request, stub: Stub) -> Result<Response> {
+    let mut req_headers = Headers::new();
+    for (name, value) in req.headers().entries() {
+        if !is_identity_header(&name) {
+            req_headers.append(&name, &value)?;
+        }
+    }
+
+    let mut req_init = RequestInit::new();
+    req_init.with_method(req.method()).with_headers(req_headers);
+
+    // note: bytes, as images are uploaded through here too.
+    let body = req.bytes().await?;
+    if !body.is_empty() {
+        req_init.with_body(Some(js_sys::Uint8Array::from(body.as_slice()).into()));
+    }
+
+    let anonymous_req = Request::new_with_init(req.url()?.as_str(), &req_init)?;
+
+    stub.fetch_with_request(anonymous_req).await
+}
+
+/// Catches up on challenge schedules, as Durable Object alarms aren't available.
+///
+/// note: the schedules were asked to run on alarms set for each `starts_at`/`ends_at`. This cron
+/// stands in for them until `worker` is upgraded to a version that has alarms, and a transition
+/// may land up to 5 minutes late for foodnotes; responses still switch on time through
+/// `cache_max_age`.
+#[event(scheduled)]
+pub async fn scheduled(_event: ScheduledEvent, env: Env, _ctx: ScheduleContext) {
+    set_panic_hook();
+
+    let applied = match ChallengesClient::new(&env) {
+        Ok(client) => client.apply_schedule_transitions().await,
+        Err(e) => Err(e),
+    };
+    if let Err(e) = applied {
+        console_error!("failed to apply challenge schedules: {}", e);
+    }
+}
+
 #[event(fetch)]
 pub async fn main(req: Request, env: Env, _ctx: Context) -> Result<Response> {
     let router = Router::new();
     set_panic_hook();
 
     let request_to_users = |_req: Request, ctx: RouteContext<()>| async move {
-        get_users_stub(&ctx)?.fetch_with_request(_req).await
+        forward_without_identity(_req, get_users_stub(&ctx)?).await
     };
 
     let request_to_challenges = |_req: Request, ctx: RouteContext<()>| async move {
-        get_challenges_stub(&ctx)?.fetch_with_request(_req).await
-    };
-
-    let request_to_challenges_for_admin = |_req: Request, ctx: RouteContext<()>| async move {
-        let users_stub = get_users_stub(&ctx)?;
-        let challenges_stub = get_challenges_stub(&ctx)?;
-        let admin_auth_req = build_admin_auth_req(&_req)?;
-
-        match users_stub.fetch_with_request(admin_auth_req).await {
-            Ok(res) => match res.status_code() {
-                200 => challenges_stub.fetch_with_request(_req).await,
-                _ => Ok(ApiError::Unauthorized.to_response()),
+        match is_admin_view(&_req) {
+            Ok(true) => request_to_challenges_for_admin(_req, ctx).await,
+            // note: signed-in users get their challenges personalised, others a cacheable list.
+            Ok(false) => match _req.headers().has("Authorization")? {
+                true => {
+                    let challenges_stub = get_challenges_stub(&ctx)?;
+                    request_with_user(_req, &ctx, challenges_stub).await
+                }
+                false => forward_without_identity(_req, get_challenges_stub(&ctx)?).await,
             },
-            Err(e) => Err(e),
+            Err(e) => Ok(e.to_response()),
         }
     };
 
-    let request_to_foodnotes = |mut _req: Request, ctx: RouteContext<()>| async move {
-        let users_stub = get_users_stub(&ctx)?;
+    let request_to_foodnotes = |_req: Request, ctx: RouteContext<()>| async move {
         let foodnotes_stub = get_foodnotes_stub(&ctx)?;
 
-        let auth_req = build_auth_req(&_req)?;
-
-        match users_stub.fetch_with_request(auth_req).await {
-            Ok(mut res) => match res.status_code() {
-                200 => {
-                    let user_id = res.json::<UserInfoDto>().await?.id;
-                    let mut req_headers = Headers::new();
-                    req_headers.append("X-Foodrhapsody-User", &user_id)?;
-
-                    let mut req_init = RequestInit::new();
-                    req_init
-                        .with_method(_req.method())
-                        .with_headers(req_headers);
+        request_with_user(_req, &ctx, foodnotes_stub).await
+    };
 
-                    let body = _req.text().await?;
-                    if !body.is_empty() {
-                        req_init.with_body(Some(JsValue::from(body)));
-                    }
+    let request_to_foodnotes_anonymously = |_req: Request, ctx: RouteContext<()>| async move {
+        forward_without_identity(_req, get_foodnotes_stub(&ctx)?).await
+    };
 
-                    let req = Request::new_with_init(_req.url()?.as_str(), &req_init)?;
+    let request_to_challenges_with_user = |_req: Request, ctx: RouteContext<()>| async move {
+        let challenges_stub = get_challenges_stub(&ctx)?;
 
-                    foodnotes_stub.fetch_with_request(req).await
-                }
-                _ => Ok(ApiError::Unauthorized.to_response()),
-            },
-            Err(e) => Err(e),
-        }
+        request_with_user(_req, &ctx, challenges_stub).await
     };
 
     router
         .get("/health", health_route)
         .get("/version", version_route)
         .post_async("/users", request_to_users)
+        .post_async("/users/guest", request_to_users)
         .get_async("/me", request_to_users)
         .get_async("/me/admin", request_to_users)
+        .get_async("/me/badges", request_to_foodnotes)
+        .get_async("/me/challenges/progress", request_to_foodnotes)
+        .get_async("/me/challenges/:id/progress", request_to_foodnotes)
+        .put_async("/me/leaderboard", request_to_foodnotes)
         .post_async("/me/token", request_to_users)
         .post_async("/place/search", |_req, ctx| async move {
             match search_place(_req, ctx).await {
@@ -115,10 +238,50 @@ pub async fn main(req: Request, env: Env, _ctx: Context) -> Result<Response> {
             }
         })
         .get_async("/challenges", request_to_challenges)
+        .get_async("/challenges/export", request_to_challenges_for_admin)
+        .get_async("/challenges/:id", request_to_challenges)
+        .get_async("/challenges/:id/stamps/:stamp_id", request_to_challenges)
         .post_async("/challenges", request_to_challenges_for_admin)
         .put_async("/challenges", request_to_challenges_for_admin)
+        .post_async("/challenges/import", request_to_challenges_for_admin)
+        .delete_async("/challenges/:id", request_to_challenges_for_admin)
+        .post_async("/challenges/:id/restore", request_to_challenges_for_admin)
+        .get_async("/challenges/:id/history", request_to_challenges_for_admin)
+        .post_async("/challenges/:id/rollback", request_to_challenges_for_admin)
+        .put_async("/challenges/:id/position", request_to_challenges_for_admin)
+        .post_async("/challenges/:id/join", request_to_challenges_with_user)
+        .delete_async("/challenges/:id/join", request_to_challenges_with_user)
+        .post_async("/challenges/:id/stamps", request_to_challenges_for_admin)
+        .put_async("/challenges/:id/stamps", request_to_challenges_for_admin)
+        .patch_async(
+            "/challenges/:id/stamps/:stamp_id",
+            request_to_challenges_for_admin,
+        )
+        .delete_async(
+            "/challenges/:id/stamps/:stamp_id",
+            request_to_challenges_for_admin,
+        )
+        .post_async(
+            "/challenges/:id/stamps/:stamp_id/claim-codes",
+            request_to_challenges_for_admin,
+        )
+        .post_async("/stamps/claim", request_to_foodnotes)
+        .get_async("/leaderboards/global", request_to_foodnotes)
+        .get_async("/leaderboards/challenges/:id", request_to_foodnotes)
+        .get_async("/feed", request_to_foodnotes_anonymously)
+        .get_async("/shared/foodnotes/:id", request_to_foodnotes_anonymously)
         .get_async("/foodnotes", request_to_foodnotes)
         .post_async("/foodnotes", request_to_foodnotes)
+        .get_async("/foodnotes/:id", request_to_foodnotes)
+        .patch_async("/foodnotes/:id", request_to_foodnotes)
+        .delete_async("/foodnotes/:id", request_to_foodnotes)
+        .post_async("/images", request_to_foodnotes)
+        .get_async("/images/:user_id/:name", |_req, ctx| async move {
+            match get_image(_req, ctx).await {
+                Ok(res) => Ok(res),
+                Err(e) => Ok(e.to_response()),
+            }
+        })
         .run(req, env)
         .await
 }
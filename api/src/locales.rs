@@ -0,0 +1,184 @@
+use std::collections::BTreeMap;
+
+use worker::Request;
+
+use crate::api_error::FieldError;
+use crate::api_result::ApiResult;
+use crate::req::ReqQuery;
+
+/// The locale of the plain text fields, which translations are given in addition to.
+pub const DEFAULT_LOCALE: &str = "ko";
+
+/// Localized variants of a text field by language tag, e.g. `{"en": "Hanwoo"}`.
+pub type Translations = BTreeMap<String, String>;
+
+/// Languages the client asks for, most preferred first: `?lang=` before `Accept-Language`.
+pub fn preferred_languages(req: &Request) -> ApiResult<Vec<String>> {
+    let mut languages = Vec::<String>::new();
+
+    if let Some(lang) = req.query_param("lang")? {
+        languages.push(lang.trim().to_lowercase());
+    }
+    if let Some(header) = req.headers().get("Accept-Language")? {
+        languages.append(&mut parse_accept_language(&header));
+    }
+
+    Ok(languages)
+}
+
+/// Parses an `Accept-Language` header into language tags ordered by their quality, e.g.
+/// `en-US,en;q=0.9,ko;q=0.8` -> `["en-us", "en", "ko"]`. Wildcards, `q=0` and qualities that
+/// aren't finite numbers are left out.
+pub fn parse_accept_language(header: &str) -> Vec<String> {
+    let mut weighted = header
+        .split(',')
+        .filter_map(|x| {
+            let mut parts = x.split(';');
+            let tag = parts.next()?.trim().to_lowercase();
+            let quality = parts
+                .find_map(|x| x.trim().strip_prefix("q="))
+                .map(|x| x.parse::<f32>().unwrap_or(0.0))
+                .unwrap_or(1.0);
+
+            match tag.is_empty() || tag == "*" || !quality.is_finite() || quality <= 0.0 {
+                true => None,
+                false => Some((tag, quality)),
+            }
+        })
+        .collect::<Vec<(String, f32)>>();
+
+    // note: the sort is stable, so tags of the same quality keep the order they were sent in.
+    weighted.sort_by(|a, b| b.1.total_cmp(&a.1));
+    weighted.into_iter().map(|(tag, _)| tag).collect()
+}
+
+/// Picks the variant of `text` for the first of `languages` it has, trying `en` for `en-us`, and
+/// falls back to `text` itself in the default locale.
+pub fn localize(text: &str, translations: &Translations, languages: &[String]) -> String {
+    for language in languages {
+        let primary = language.split('-').next().unwrap_or(language);
+        for tag in [language.as_str(), primary] {
+            if tag.eq_ignore_ascii_case(DEFAULT_LOCALE) {
+                return text.to_owned();
+            }
+
+            let translated = translations
+                .iter()
+                .find(|(k, _)| k.eq_ignore_ascii_case(tag))
+                .map(|(_, v)| v);
+            if let Some(translated) = translated {
+                return translated.to_owned();
+            }
+        }
+    }
+
+    text.to_owned()
+}
+
+pub fn validate_translations(field: &str, translations: &Translations) -> Vec<FieldError> {
+    let mut errors = Vec::<FieldError>::new();
+
+    for (tag, text) in translations {
+        let field = format!("{}.{}", field, tag);
+        if !is_language_tag(tag) {
+            errors.push(FieldError::new(&field, "invalid language tag"));
+        } else if text.trim().is_empty() {
+            errors.push(FieldError::new(&field, "required"));
+        }
+    }
+
+    errors
+}
+
+fn is_language_tag(value: &str) -> bool {
+    !value.is_empty()
+        && value
+            .split('-')
+            .all(|x| (1..=8).contains(&x.len()) && x.chars().all(|x| x.is_ascii_alphanumeric()))
+}
+
+#[cfg(test)]
+mod locales_tests {
+    use super::*;
+
+    fn translations() -> Translations {
+        let mut translations = Translations::new();
+        translations.insert("en".to_string(), "Hanwoo".to_string());
+        translations.insert("ja".to_string(), "韓牛".to_string());
+
+        translations
+    }
+
+    fn languages(tags: &[&str]) -> Vec<String> {
+        tags.iter().map(|x| x.to_string()).collect()
+    }
+
+    #[test]
+    fn should_parse_accept_language_by_quality() {
+        assert_eq!(
+            parse_accept_language("ko;q=0.8, en-US, en;q=0.9, *;q=0.1"),
+            vec!["en-us", "en", "ko"]
+        );
+        assert_eq!(parse_accept_language("fr;q=0, ja"), vec!["ja"]);
+        assert!(parse_accept_language("").is_empty());
+    }
+
+    #[test]
+    fn should_leave_out_languages_with_invalid_quality() {
+        assert_eq!(
+            parse_accept_language("en;q=NaN, fr;q=inf, ko;q=0.5"),
+            vec!["ko"]
+        );
+        assert!(parse_accept_language("en;q=NaN").is_empty());
+    }
+
+    #[test]
+    fn should_localize_to_first_available_language() {
+        let translations = translations();
+
+        assert_eq!(
+            localize("한우", &translations, &languages(&["en"])),
+            "Hanwoo"
+        );
+        assert_eq!(
+            localize("한우", &translations, &languages(&["EN-gb"])),
+            "Hanwoo"
+        );
+        assert_eq!(
+            localize("한우", &translations, &languages(&["fr", "ja"])),
+            "韓牛"
+        );
+        assert_eq!(
+            localize("한우", &translations, &languages(&["ko", "en"])),
+            "한우"
+        );
+    }
+
+    #[test]
+    fn should_fall_back_to_default_locale() {
+        assert_eq!(
+            localize("한우", &translations(), &languages(&["fr"])),
+            "한우"
+        );
+        assert_eq!(localize("한우", &translations(), &[]), "한우");
+        assert_eq!(
+            localize("한우", &Translations::new(), &languages(&["en"])),
+            "한우"
+        );
+    }
+
+    #[test]
+    fn should_validate_translations() {
+        let mut translations = translations();
+        translations.insert("en_US".to_string(), "Hanwoo".to_string());
+        translations.insert("zh-Hant".to_string(), " ".to_string());
+
+        assert_eq!(
+            validate_translations("name_translations", &translations),
+            vec![
+                FieldError::new("name_translations.en_US", "invalid language tag"),
+                FieldError::new("name_translations.zh-Hant", "required"),
+            ]
+        );
+    }
+}
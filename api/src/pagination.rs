@@ -0,0 +1,152 @@
+use serde::{Deserialize, Serialize};
+
+use crate::api_error::ApiError;
+use crate::api_result::ApiResult;
+
+pub const DEFAULT_PAGE_LIMIT: usize = 20;
+pub const MAX_PAGE_LIMIT: usize = 100;
+
+pub fn parse_page_limit(value: Option<&str>) -> usize {
+    value
+        .and_then(|x| x.parse::<usize>().ok())
+        .unwrap_or(DEFAULT_PAGE_LIMIT)
+        .clamp(1, MAX_PAGE_LIMIT)
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PageOrder {
+    #[default]
+    Newest,
+    Oldest,
+}
+
+impl PageOrder {
+    pub fn parse(value: Option<&str>) -> ApiResult<Self> {
+        match value {
+            None | Some("newest") => Ok(PageOrder::Newest),
+            Some("oldest") => Ok(PageOrder::Oldest),
+            Some(_) => Err(ApiError::BadRequest("invalid order".to_string())),
+        }
+    }
+}
+
+/// A position in a list of storage keys sharing a prefix. Clients only get it hex-encoded, so they
+/// don't come to rely on how the keys look.
+pub fn encode_cursor(prefix: &str, key: &str) -> String {
+    let suffix = key.strip_prefix(prefix).unwrap_or(key);
+
+    suffix.bytes().map(|x| format!("{:02x}", x)).collect()
+}
+
+pub fn decode_cursor(prefix: &str, cursor: &str) -> ApiResult<String> {
+    let invalid = || ApiError::BadRequest("invalid cursor".to_string());
+
+    if cursor.is_empty() || cursor.len() % 2 == 1 {
+        return Err(invalid());
+    }
+
+    let bytes = (0..cursor.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(cursor.get(i..i + 2).unwrap_or(""), 16))
+        .collect::<Result<Vec<u8>, _>>()
+        .map_err(|_| invalid())?;
+    let suffix = String::from_utf8(bytes).map_err(|_| invalid())?;
+    if !suffix
+        .chars()
+        .all(|x| x.is_ascii_alphanumeric() || x == '_')
+    {
+        return Err(invalid());
+    }
+
+    Ok(format!("{}{}", prefix, suffix))
+}
+
+/// Bounds of storage keys to list, `start` inclusive and `end` exclusive.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct KeyRange {
+    pub start: Option<String>,
+    pub end: Option<String>,
+}
+
+impl KeyRange {
+    /// Narrows the range to the keys after `key` in `order`, to continue from a cursor.
+    pub fn after(mut self, key: &str, order: PageOrder) -> Self {
+        match order {
+            PageOrder::Oldest => {
+                // note: the smallest key greater than `key`.
+                let start = format!("{}\u{0}", key);
+                if !matches!(self.start.as_deref(), Some(x) if x >= start.as_str()) {
+                    self.start = Some(start);
+                }
+            }
+            PageOrder::Newest => {
+                if !matches!(self.end.as_deref(), Some(x) if x <= key) {
+                    self.end = Some(key.to_owned());
+                }
+            }
+        }
+
+        self
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Page<T> {
+    pub items: Vec<T>,
+    pub next_cursor: Option<String>,
+}
+
+#[cfg(test)]
+mod pagination_tests {
+    use super::*;
+
+    #[test]
+    fn should_parse_page_limit() {
+        assert_eq!(parse_page_limit(None), DEFAULT_PAGE_LIMIT);
+        assert_eq!(parse_page_limit(Some("5")), 5);
+        assert_eq!(parse_page_limit(Some("0")), 1);
+        assert_eq!(parse_page_limit(Some("1000")), MAX_PAGE_LIMIT);
+    }
+
+    #[test]
+    fn should_parse_page_order() {
+        assert_eq!(PageOrder::parse(None).unwrap(), PageOrder::Newest);
+        assert_eq!(PageOrder::parse(Some("oldest")).unwrap(), PageOrder::Oldest);
+        assert!(PageOrder::parse(Some("random")).is_err());
+    }
+
+    #[test]
+    fn should_decode_encoded_cursor() {
+        let cursor = encode_cursor("author_alice_", "author_alice_000000000010_a");
+
+        assert_eq!(
+            decode_cursor("author_alice_", &cursor).unwrap(),
+            "author_alice_000000000010_a"
+        );
+    }
+
+    #[test]
+    fn should_not_decode_invalid_cursor() {
+        assert!(decode_cursor("author_alice_", "").is_err());
+        assert!(decode_cursor("author_alice_", "zz").is_err());
+        // note: a cursor can't reach keys outside the prefix.
+        let escaping = encode_cursor("", "../bob");
+        assert!(decode_cursor("author_alice_", &escaping).is_err());
+    }
+
+    #[test]
+    fn should_narrow_range_after_cursor() {
+        let range = KeyRange {
+            start: Some("a_1".to_string()),
+            end: None,
+        };
+
+        let oldest = range.clone().after("a_5", PageOrder::Oldest);
+        assert_eq!(oldest.start.as_deref(), Some("a_5\u{0}"));
+
+        let newest = range.after("a_5", PageOrder::Newest);
+        assert_eq!(newest.start.as_deref(), Some("a_1"));
+        assert_eq!(newest.end.as_deref(), Some("a_5"));
+    }
+}
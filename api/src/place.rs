@@ -7,7 +7,7 @@ use crate::res::response_with_cache;
 
 const KAKAO_MAP_API: &str = "https://dapi.kakao.com/v2/local/search/keyword.json";
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct PlaceDocument {
     pub id: String,
     pub place_name: String,
@@ -0,0 +1,192 @@
+use serde::{Deserialize, Serialize};
+
+use crate::challenges::Challenge;
+use crate::foodnotes::Foodnote;
+
+/// A stamp collected by an author, with every foodnote that claims it.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CollectedStamp {
+    pub stamp_id: String,
+    pub first_collected_at: i64,
+    pub foodnote_ids: Vec<String>,
+}
+
+impl CollectedStamp {
+    pub fn new(foodnote: &Foodnote) -> Self {
+        Self {
+            stamp_id: foodnote.stamp_id.to_owned(),
+            first_collected_at: foodnote.timestamp,
+            foodnote_ids: vec![foodnote.id.to_owned()],
+        }
+    }
+
+    pub fn add(&mut self, foodnote: &Foodnote) -> &mut Self {
+        if !self.foodnote_ids.contains(&foodnote.id) {
+            self.foodnote_ids.push(foodnote.id.to_owned());
+        }
+        self.first_collected_at = self.first_collected_at.min(foodnote.timestamp);
+
+        self
+    }
+}
+
+/// Groups foodnotes by the stamp they claim, leaving out those that don't count toward progress.
+pub fn collect_stamps(foodnotes: &[Foodnote]) -> Vec<CollectedStamp> {
+    let mut collected = Vec::<CollectedStamp>::new();
+
+    for foodnote in foodnotes.iter().filter(|x| x.counts_toward_progress()) {
+        match collected
+            .iter_mut()
+            .find(|x| x.stamp_id == foodnote.stamp_id)
+        {
+            Some(x) => {
+                x.add(foodnote);
+            }
+            None => collected.push(CollectedStamp::new(foodnote)),
+        }
+    }
+
+    collected
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ChallengeProgress {
+    pub challenge_id: String,
+    pub collected: Vec<CollectedStamp>,
+    pub remaining_stamp_ids: Vec<String>,
+    pub percentage: u32,
+    pub first_collected_at: Option<i64>,
+}
+
+impl ChallengeProgress {
+    pub fn new(challenge: &Challenge, collected_stamps: &[CollectedStamp]) -> Self {
+        let mut collected = Vec::<CollectedStamp>::new();
+        let mut remaining_stamp_ids = Vec::<String>::new();
+
+        for stamp in &challenge.stamps {
+            match collected_stamps.iter().find(|x| x.stamp_id == stamp.id) {
+                Some(x) => collected.push(x.clone()),
+                None => remaining_stamp_ids.push(stamp.id.to_owned()),
+            }
+        }
+
+        let percentage = match challenge.stamps.len() {
+            0 => 0,
+            total => (collected.len() * 100 / total) as u32,
+        };
+        let first_collected_at = collected.iter().map(|x| x.first_collected_at).min();
+
+        Self {
+            challenge_id: challenge.id.to_owned(),
+            collected,
+            remaining_stamp_ids,
+            percentage,
+            first_collected_at,
+        }
+    }
+
+    pub fn is_completed(&self) -> bool {
+        !self.collected.is_empty() && self.remaining_stamp_ids.is_empty()
+    }
+}
+
+/// Progress in the challenges active at `now`, the same ones a single challenge is found among.
+pub fn list_active_progress(
+    challenges: &[Challenge],
+    collected_stamps: &[CollectedStamp],
+    now: i64,
+) -> Vec<ChallengeProgress> {
+    challenges
+        .iter()
+        .filter(|x| x.is_active_at(now))
+        .map(|x| ChallengeProgress::new(x, collected_stamps))
+        .collect()
+}
+
+#[cfg(test)]
+mod progress_tests {
+    use super::*;
+    use crate::challenges::ChallengeStatus;
+    use crate::test_fixtures::{challenge, foodnote};
+
+    #[test]
+    fn should_collect_stamps_by_first_foodnote() {
+        let foodnotes = vec![foodnote("a", 20), foodnote("b", 30), foodnote("a", 10)];
+        let collected = collect_stamps(&foodnotes);
+
+        assert_eq!(collected.len(), 2);
+        assert_eq!(collected[0].stamp_id, "a");
+        assert_eq!(collected[0].first_collected_at, 10);
+        assert_eq!(collected[0].foodnote_ids.len(), 2);
+    }
+
+    #[test]
+    fn should_not_collect_flagged_foodnotes() {
+        let mut flagged = foodnote("b", 10);
+        flagged.place_mismatch = true;
+        let collected = collect_stamps(&[foodnote("a", 20), flagged]);
+
+        assert_eq!(collected.len(), 1);
+        assert_eq!(collected[0].stamp_id, "a");
+    }
+
+    #[test]
+    fn should_collect_only_verified_visits_when_required() {
+        let mut unverified = foodnote("a", 10);
+        unverified.verified_visit_required = true;
+        let mut verified = foodnote("b", 10);
+        verified.verified_visit_required = true;
+        verified.verified_visit = true;
+        let collected = collect_stamps(&[unverified, verified]);
+
+        assert_eq!(collected.len(), 1);
+        assert_eq!(collected[0].stamp_id, "b");
+    }
+
+    #[test]
+    fn should_calculate_challenge_progress() {
+        let collected = collect_stamps(&[foodnote("a", 20), foodnote("x", 10)]);
+        let progress = ChallengeProgress::new(&challenge(&["a", "b", "c"]), &collected);
+
+        assert_eq!(progress.collected.len(), 1);
+        assert_eq!(progress.remaining_stamp_ids, vec!["b", "c"]);
+        assert_eq!(progress.percentage, 33);
+        assert_eq!(progress.first_collected_at, Some(20));
+    }
+
+    #[test]
+    fn should_complete_when_every_stamp_is_collected() {
+        let collected = collect_stamps(&[foodnote("a", 20), foodnote("b", 10)]);
+        let progress = ChallengeProgress::new(&challenge(&["a", "b"]), &collected);
+        let partial = ChallengeProgress::new(&challenge(&["a", "c"]), &collected);
+
+        assert_eq!(progress.percentage, 100);
+        assert!(progress.is_completed());
+        assert!(!partial.is_completed());
+    }
+
+    #[test]
+    fn should_be_empty_progress_for_challenge_without_stamps() {
+        let collected = collect_stamps(&[foodnote("a", 20)]);
+        let progress = ChallengeProgress::new(&challenge(&[]), &collected);
+
+        assert_eq!(progress.percentage, 0);
+        assert!(progress.collected.is_empty());
+        assert!(!progress.is_completed());
+    }
+
+    #[test]
+    fn should_list_progress_of_active_challenges_only() {
+        let collected = collect_stamps(&[foodnote("a", 20)]);
+        let mut draft = challenge(&["a"]);
+        draft.status = ChallengeStatus::Draft;
+        let mut upcoming = challenge(&["a"]);
+        upcoming.starts_at = Some(200);
+        let active = challenge(&["a", "b"]);
+
+        let progress = list_active_progress(&[draft, upcoming, active.clone()], &collected, 100);
+
+        assert_eq!(progress.len(), 1);
+        assert_eq!(progress[0].challenge_id, active.id);
+    }
+}
@@ -21,3 +21,39 @@ impl ParseReqJson for Request {
         }
     }
 }
+
+pub trait ReqQuery {
+    fn query_param(&self, key: &str) -> ApiResult<Option<String>>;
+}
+
+impl ReqQuery for Request {
+    fn query_param(&self, key: &str) -> ApiResult<Option<String>> {
+        let url = self.url()?;
+        let value = url
+            .query_pairs()
+            .find(|(k, _)| k == key)
+            .map(|(_, v)| v.into_owned());
+
+        Ok(value)
+    }
+}
+
+/// Splits a request path into its non-empty segments, e.g. `/challenges/a` -> `["challenges", "a"]`.
+pub fn path_segments(path: &str) -> Vec<&str> {
+    path.split('/').filter(|x| !x.is_empty()).collect()
+}
+
+#[cfg(test)]
+mod path_segments_tests {
+    use super::*;
+
+    #[test]
+    fn should_split_path_into_segments() {
+        assert_eq!(
+            path_segments("/challenges/abc/stamps/def"),
+            vec!["challenges", "abc", "stamps", "def"]
+        );
+        assert_eq!(path_segments("/challenges/"), vec!["challenges"]);
+        assert!(path_segments("/").is_empty());
+    }
+}
@@ -0,0 +1,72 @@
+use crate::challenges::{Challenge, ChallengeStatus, CreateChallengeDto, Stamp};
+use crate::foodnotes::{CreateFoodnoteDto, Foodnote};
+use crate::locales::Translations;
+use crate::place::PlaceDocument;
+use crate::visibility::Visibility;
+
+pub fn place() -> PlaceDocument {
+    serde_json::from_str(
+        r#"{
+            "id": "234127678",
+            "place_name": "키친마이야르",
+            "category_name": "음식점 > 양식",
+            "category_group_code": "FD6",
+            "category_group_name": "음식점",
+            "phone": "",
+            "address_name": "서울 강남구 신사동 644-6",
+            "road_address_name": "",
+            "x": "127.035423195622",
+            "y": "37.5266092359544",
+            "place_url": "",
+            "distance": ""
+        }"#,
+    )
+    .unwrap()
+}
+
+pub fn foodnote(stamp_id: &str, timestamp: i64) -> Foodnote {
+    let mut foodnote = Foodnote::new(
+        "alice",
+        CreateFoodnoteDto {
+            stamp_id: stamp_id.to_string(),
+            author_id: None,
+            text: "".to_string(),
+            place: place(),
+            visited_at: None,
+            img_urls: vec![],
+            visibility: Visibility::Private,
+            check_in: None,
+        },
+    );
+    foodnote.timestamp = timestamp;
+
+    foodnote
+}
+
+pub fn challenge(stamp_ids: &[&str]) -> Challenge {
+    let stamps = stamp_ids
+        .iter()
+        .map(|id| Stamp {
+            id: id.to_string(),
+            title: id.to_string(),
+            title_translations: Translations::new(),
+            description: "".to_string(),
+            description_translations: Translations::new(),
+            img_url: "".to_string(),
+            constraints: None,
+        })
+        .collect();
+
+    Challenge::new(&CreateChallengeDto {
+        name: "한우".to_string(),
+        name_translations: Translations::new(),
+        stamps,
+        status: ChallengeStatus::Published,
+        starts_at: None,
+        ends_at: None,
+        cover_img_url: "".to_string(),
+        tags: vec![],
+        difficulty: None,
+        points: 0,
+    })
+}
@@ -1,4 +1,4 @@
-use chrono::Duration;
+use chrono::{Duration, Utc};
 use serde::{Deserialize, Serialize};
 use serde_json::json;
 use worker::*;
@@ -6,7 +6,7 @@ use worker::*;
 use crate::api_error::ApiError;
 use crate::api_result::ApiResult;
 use crate::auth::{authorize_access_token, authorize_refresh_token};
-use crate::durable::DurableStorageFind;
+use crate::durable::{fetch_internal, get_durable_stub, DurableStorageFind};
 use crate::jwt::Jwt;
 use crate::oauth::OAuthProvider;
 use crate::req::ParseReqJson;
@@ -14,6 +14,7 @@ use crate::res::response;
 use crate::uid;
 
 const ADMIN_EMAILS: [&str; 1] = ["seokju.me@kakao.com"];
+const GUESTS_PER_HOUR: u32 = 10;
 
 pub fn user_id_key(id: &str) -> String {
     format!("id_{}", id)
@@ -23,12 +24,39 @@ pub fn user_email_key(email: &str) -> String {
     format!("email_{}", email)
 }
 
+pub fn guest_throttle_key(ip: &str) -> String {
+    format!("guest_throttle_{}", ip)
+}
+
+/// How many guests were created from an address within the hour starting at `window`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct GuestThrottle {
+    pub window: i64,
+    pub count: u32,
+}
+
+impl GuestThrottle {
+    /// Counts one more guest at `now`, or `None` if the address already made enough this hour.
+    pub fn hit(previous: Option<GuestThrottle>, now: i64) -> Option<GuestThrottle> {
+        let window = now - now.rem_euclid(3600);
+
+        match previous {
+            Some(x) if x.window == window && x.count >= GUESTS_PER_HOUR => None,
+            Some(x) if x.window == window => Some(GuestThrottle {
+                window,
+                count: x.count + 1,
+            }),
+            _ => Some(GuestThrottle { window, count: 1 }),
+        }
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct User {
     pub id: String,
-    pub email: String,
+    pub email: Option<String>,
     pub name: Option<String>,
-    pub oauth_provider: String,
+    pub oauth_provider: Option<String>,
     pub access_token: Option<String>,
     pub refresh_token: Option<String>,
 }
@@ -36,7 +64,20 @@ pub struct User {
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct UserInfoDto {
     pub id: String,
-    pub email: String,
+    pub email: Option<String>,
+    pub is_guest: bool,
+}
+
+/// What other users may see about a user.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UserProfileDto {
+    pub id: String,
+    pub name: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UserProfilesDto {
+    pub ids: Vec<String>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -52,24 +93,53 @@ impl User {
 
         User {
             id,
-            email: String::from(&dto.email),
+            email: Some(dto.email.to_owned()),
             name: dto.name.clone(),
-            oauth_provider: dto.oauth_provider.clone(),
+            oauth_provider: Some(dto.oauth_provider.to_owned()),
+            access_token: None,
+            refresh_token: None,
+        }
+    }
+
+    pub fn new_guest() -> Self {
+        let id = uid!();
+
+        User {
+            id,
+            email: None,
+            name: None,
+            oauth_provider: None,
             access_token: None,
             refresh_token: None,
         }
     }
 
+    pub fn is_guest(&self) -> bool {
+        self.oauth_provider.is_none()
+    }
+
     pub fn is_admin(&self) -> bool {
-        ADMIN_EMAILS.iter().any(|x| x == &self.email.as_str())
+        match &self.email {
+            Some(email) => ADMIN_EMAILS.iter().any(|x| x == &email.as_str()),
+            None => false,
+        }
     }
 
     pub fn id_key(&self) -> String {
         user_id_key(&self.id)
     }
 
-    pub fn email_key(&self) -> String {
-        user_email_key(&self.email)
+    pub fn email_key(&self) -> Option<String> {
+        self.email.as_ref().map(|email| user_email_key(email))
+    }
+
+    /// Links an OAuth identity to a guest user, keeping its id (and so everything owned by it).
+    pub fn upgrade(&mut self, dto: &CreateUserDto) -> &mut Self {
+        self.email = Some(dto.email.to_owned());
+        self.name = dto.name.clone();
+        self.oauth_provider = Some(dto.oauth_provider.to_owned());
+
+        self
     }
 
     pub fn with_refresh_token(&mut self, refresh_token: &str) -> &mut Self {
@@ -87,7 +157,15 @@ impl User {
     pub fn to_info_dto(&self) -> UserInfoDto {
         UserInfoDto {
             id: self.id.to_owned(),
-            email: self.email.to_owned(),
+            email: self.email.clone(),
+            is_guest: self.is_guest(),
+        }
+    }
+
+    pub fn to_profile_dto(&self) -> UserProfileDto {
+        UserProfileDto {
+            id: self.id.to_owned(),
+            name: self.name.clone(),
         }
     }
 
@@ -120,6 +198,41 @@ impl UserClaims {
     }
 }
 
+/// Reads users from other durable objects.
+pub struct UsersClient {
+    stub: Stub,
+}
+
+impl UsersClient {
+    pub fn new(env: &Env) -> ApiResult<Self> {
+        let stub = get_durable_stub(env, "USERS")?;
+
+        Ok(Self { stub })
+    }
+
+    pub async fn list_profiles(&self, ids: Vec<String>) -> ApiResult<Vec<UserProfileDto>> {
+        #[derive(Deserialize)]
+        struct UserProfiles {
+            users: Vec<UserProfileDto>,
+        }
+
+        if ids.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let dto = UserProfilesDto { ids };
+        let res = fetch_internal::<_, UserProfiles>(
+            &self.stub,
+            Method::Post,
+            "/internal/users/profiles",
+            Some(&dto),
+        )
+        .await?;
+
+        Ok(res.users)
+    }
+}
+
 #[durable_object]
 pub struct Users {
     state: State,
@@ -206,14 +319,43 @@ impl Users {
 
         s.put(&refresh_id, &user.id).await?;
         s.put(&user.id_key(), &user).await?;
-        s.put(&user.email_key(), &user.id).await?;
+        if let Some(email_key) = user.email_key() {
+            s.put(&email_key, &user.id).await?;
+        }
+
+        Ok(user)
+    }
+
+    /// Upgrades a guest with a verified OAuth identity, unless the identity already belongs to
+    /// another user.
+    pub async fn upgrade_guest(&self, mut user: User, dto: &CreateUserDto) -> ApiResult<User> {
+        // note: other requests only run while fetching, so nothing claims the email in between.
+        if self.find_by_email(&dto.email).await?.is_some() {
+            return Err(ApiError::UserEmailDuplicated);
+        }
+
+        user.upgrade(dto);
+
+        let mut s = self.state.storage();
+
+        if let Some(email_key) = user.email_key() {
+            s.put(&email_key, &user.id).await?;
+        }
+        s.put(&user.id_key(), &user).await?;
+
+        let user = self.update_refresh_token(user).await?;
+        let user = self.update_access_token(user).await?;
 
         Ok(user)
     }
 
+    /// Issues a new refresh token, dropping the index entry of the one it replaces.
     pub async fn update_refresh_token(&self, mut user: User) -> ApiResult<User> {
         let (refresh_id, refresh_token) = self.create_refresh_token()?;
 
+        if let Some(previous_id) = self.find_refresh_id(&user)? {
+            self.state.storage().delete(&previous_id).await?;
+        }
         user.with_refresh_token(&refresh_token);
         self.state.storage().put(&refresh_id, &user.id).await?;
         self.state.storage().put(&user.id_key(), &user).await?;
@@ -230,6 +372,18 @@ impl Users {
         Ok(user)
     }
 
+    /// The id the current refresh token of the user is indexed by, even when it has expired.
+    fn find_refresh_id(&self, user: &User) -> ApiResult<Option<String>> {
+        let jwt = self.get_jwt_for_refresh_token()?;
+        let refresh_id = user
+            .refresh_token
+            .as_ref()
+            .and_then(|x| jwt.verify_integrity::<UserClaims>(x).ok())
+            .map(|x| x.claims().custom.subject.to_owned());
+
+        Ok(refresh_id)
+    }
+
     fn create_refresh_token(&self) -> ApiResult<(String, String)> {
         let jwt = self.get_jwt_for_refresh_token()?;
         let refresh_id = uid!();
@@ -260,33 +414,82 @@ pub struct CreateUserDto {
     pub oauth_provider: String,
 }
 
+/// Creates a guest, at most `GUESTS_PER_HOUR` an hour for each client address.
+// TODO: remove guests that never sign in again. Users need a last-seen time for that, and what
+// the guests own in Foodnotes has to go with them.
+pub async fn create_guest_user(users: &Users, req: Request) -> ApiResult<User> {
+    // note: the header is set by Cloudflare, so it's missing only when running locally.
+    let ip = req
+        .headers()
+        .get("CF-Connecting-IP")?
+        .unwrap_or_else(|| "unknown".to_owned());
+    let key = guest_throttle_key(&ip);
+
+    let mut storage = users.state.storage();
+    let previous = storage.find::<GuestThrottle>(&key).await?;
+    let throttle =
+        GuestThrottle::hit(previous, Utc::now().timestamp()).ok_or(ApiError::TooManyRequests)?;
+    storage.put(&key, &throttle).await?;
+
+    users.create(User::new_guest()).await
+}
+
+/// Signs in with an OAuth identity. When the request carries a valid guest's access token, the
+/// guest is upgraded in place instead; if the identity already belongs to another user, the
+/// upgrade is rejected with `UserEmailDuplicated` and the guest stays as it is.
 pub async fn create_or_update_user(users: &Users, mut req: Request) -> ApiResult<User> {
+    let guest = find_guest_for_upgrade(users, &req).await;
     let dto = req.parse_json::<CreateUserDto>().await?;
 
     let provider = OAuthProvider::from_str(&dto.oauth_provider)?;
     provider.verify_token(&dto.oauth_token, &dto.email).await?;
 
-    let exists_user = users.find_by_email(&dto.email).await?;
-    if let Some(user) = exists_user {
-        let user = users.update_refresh_token(user).await?;
-        let user = users.update_access_token(user).await?;
+    if let Some(guest) = guest {
+        return users.upgrade_guest(guest, &dto).await;
+    }
+
+    match users.find_by_email(&dto.email).await? {
+        Some(user) => {
+            let user = users.update_refresh_token(user).await?;
+            let user = users.update_access_token(user).await?;
 
-        return Ok(user);
+            Ok(user)
+        }
+        None => users.create(User::new(&dto)).await,
     }
+}
 
-    let user = users.create(User::new(&dto)).await?;
+/// The guest to upgrade on sign-in. A missing, stale or non-guest token signs in as usual.
+async fn find_guest_for_upgrade(users: &Users, req: &Request) -> Option<User> {
+    let user = authorize_access_token(users, req).await.ok()?;
 
-    Ok(user)
+    match user.is_guest() {
+        true => Some(user),
+        false => None,
+    }
+}
+
+pub async fn list_user_profiles(users: &Users, mut req: Request) -> ApiResult<Vec<UserProfileDto>> {
+    let dto = req.parse_json::<UserProfilesDto>().await?;
+    let mut profiles = Vec::<UserProfileDto>::new();
+
+    for id in dto.ids {
+        if let Some(user) = users.find_by_id(&id).await? {
+            profiles.push(user.to_profile_dto());
+        }
+    }
+
+    Ok(profiles)
 }
 
 pub async fn recognize_me(users: &Users, req: Request) -> ApiResult<User> {
-    let user = authorize_access_token(&users, req).await?;
+    let user = authorize_access_token(users, &req).await?;
 
     Ok(user)
 }
 
 pub async fn update_my_token(users: &Users, req: Request) -> ApiResult<User> {
-    let user = authorize_refresh_token(&users, req).await?;
+    let user = authorize_refresh_token(users, &req).await?;
 
     // TODO(@seokju-na): Renew only when refresh_token expires less than 1 month
     let user = users.update_refresh_token(user).await?;
@@ -324,6 +527,14 @@ impl DurableObject for Users {
             };
         }
 
+        // POST /users/guest
+        if method == Method::Post && &path == "/users/guest" {
+            return match create_guest_user(self, req).await {
+                Ok(user) => response(&json!(user.to_token_dto())),
+                Err(e) => Ok(e.to_response()),
+            };
+        }
+
         // POST /users
         if method == Method::Post && &path == "/users" {
             return match create_or_update_user(self, req).await {
@@ -340,6 +551,14 @@ impl DurableObject for Users {
             };
         }
 
+        // POST /internal/users/profiles
+        if method == Method::Post && &path == "/internal/users/profiles" {
+            return match list_user_profiles(self, req).await {
+                Ok(profiles) => response(&json!({ "users": profiles })),
+                Err(e) => Ok(e.to_response()),
+            };
+        }
+
         Response::error("not found", 404)
     }
 }
@@ -359,9 +578,9 @@ mod user_tests {
         let user = User::new(&data);
 
         assert_eq!(user.id.len(), 21);
-        assert_eq!(user.email, "seokju.me@gmail.com");
+        assert_eq!(user.email.unwrap(), "seokju.me@gmail.com");
         assert_eq!(user.name.unwrap(), "Seokju Na");
-        assert_eq!(user.oauth_provider, "kakao");
+        assert_eq!(user.oauth_provider.unwrap(), "kakao");
     }
 
     #[test]
@@ -375,8 +594,55 @@ mod user_tests {
         let user = User::new(&data);
 
         assert_eq!(user.id.len(), 21);
-        assert_eq!(user.email, "test@test.com");
+        assert_eq!(user.email.unwrap(), "test@test.com");
         assert_eq!(user.name.unwrap_or(String::from("NO_NAMED")), "NO_NAMED");
-        assert_eq!(user.oauth_provider, "kakao");
+        assert_eq!(user.oauth_provider.unwrap(), "kakao");
+    }
+
+    #[test]
+    fn should_throttle_guests_within_the_hour() {
+        let mut throttle = None;
+        for _ in 0..GUESTS_PER_HOUR {
+            throttle = GuestThrottle::hit(throttle, 7200);
+            assert!(throttle.is_some());
+        }
+
+        assert_eq!(GuestThrottle::hit(throttle.clone(), 10799), None);
+        assert_eq!(
+            GuestThrottle::hit(throttle, 10800),
+            Some(GuestThrottle {
+                window: 10800,
+                count: 1
+            })
+        );
+    }
+
+    #[test]
+    fn should_create_guest_user() {
+        let user = User::new_guest();
+
+        assert_eq!(user.id.len(), 21);
+        assert!(user.is_guest());
+        assert!(!user.is_admin());
+        assert!(user.email_key().is_none());
+    }
+
+    #[test]
+    fn should_upgrade_guest_user_keeping_id() {
+        let mut user = User::new_guest();
+        let guest_id = user.id.clone();
+        let data = CreateUserDto {
+            email: "test@test.com".to_string(),
+            name: Some("Tester".to_string()),
+            oauth_token: "token".to_string(),
+            oauth_provider: "kakao".to_string(),
+        };
+
+        user.upgrade(&data);
+
+        assert_eq!(user.id, guest_id);
+        assert!(!user.is_guest());
+        assert_eq!(user.email_key().unwrap(), "email_test@test.com");
+        assert_eq!(user.to_info_dto().email.unwrap(), "test@test.com");
     }
 }
@@ -0,0 +1,138 @@
+use serde::{Deserialize, Serialize};
+
+/// Who may read a foodnote besides its author. It is enforced on single reads, shared links and
+/// the feed; there are no place or profile listings of foodnotes yet.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Visibility {
+    #[default]
+    Private,
+    /// Meant for the followers of the author. There is no following yet, so it is refused for new
+    /// foodnotes and records holding it are only visible to the author.
+    Followers,
+    /// Anyone with the share token of the foodnote.
+    LinkOnly,
+    Public,
+}
+
+impl Visibility {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "private" => Some(Visibility::Private),
+            "link_only" => Some(Visibility::LinkOnly),
+            "public" => Some(Visibility::Public),
+            _ => None,
+        }
+    }
+
+    /// `visibility` wins over the `is_public` of older clients and records.
+    pub fn from_fields(visibility: Option<Visibility>, is_public: Option<bool>) -> Self {
+        match (visibility, is_public) {
+            (Some(x), _) => x,
+            (None, Some(true)) => Visibility::Public,
+            (None, _) => Visibility::Private,
+        }
+    }
+
+    pub fn is_public(&self) -> bool {
+        *self == Visibility::Public
+    }
+
+    /// Whether foodnotes may be given the visibility, which `followers` can't until following
+    /// exists.
+    pub fn is_accepted(&self) -> bool {
+        *self != Visibility::Followers
+    }
+}
+
+/// (De)serializes a visibility flattened as both `visibility` and the older `is_public`.
+pub mod visibility_fields {
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+    use super::Visibility;
+
+    #[derive(Serialize)]
+    struct Fields {
+        visibility: Visibility,
+        is_public: bool,
+    }
+
+    #[derive(Deserialize)]
+    struct OptionalFields {
+        visibility: Option<Visibility>,
+        is_public: Option<bool>,
+    }
+
+    pub fn serialize<S: Serializer>(
+        visibility: &Visibility,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        Fields {
+            visibility: *visibility,
+            is_public: visibility.is_public(),
+        }
+        .serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Visibility, D::Error> {
+        let fields = OptionalFields::deserialize(deserializer)?;
+
+        Ok(Visibility::from_fields(fields.visibility, fields.is_public))
+    }
+}
+
+#[cfg(test)]
+mod visibility_tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[derive(Debug, Serialize, Deserialize)]
+    struct Note {
+        id: String,
+        #[serde(flatten, with = "visibility_fields")]
+        visibility: Visibility,
+    }
+
+    #[test]
+    fn should_prefer_visibility_over_is_public() {
+        assert_eq!(
+            Visibility::from_fields(Some(Visibility::LinkOnly), Some(true)),
+            Visibility::LinkOnly
+        );
+        assert_eq!(
+            Visibility::from_fields(None, Some(true)),
+            Visibility::Public
+        );
+        assert_eq!(Visibility::from_fields(None, None), Visibility::Private);
+    }
+
+    #[test]
+    fn should_read_older_json_with_is_public() {
+        let note = serde_json::from_value::<Note>(json!({ "id": "a", "is_public": true })).unwrap();
+
+        assert_eq!(note.visibility, Visibility::Public);
+    }
+
+    #[test]
+    fn should_write_both_visibility_and_is_public() {
+        let note = Note {
+            id: "a".to_string(),
+            visibility: Visibility::LinkOnly,
+        };
+
+        assert_eq!(
+            serde_json::to_value(&note).unwrap(),
+            json!({ "id": "a", "visibility": "link_only", "is_public": false })
+        );
+    }
+
+    #[test]
+    fn should_parse_visibility() {
+        assert_eq!(Visibility::parse("link_only"), Some(Visibility::LinkOnly));
+        assert_eq!(Visibility::parse("followers"), None);
+        assert_eq!(Visibility::parse("everyone"), None);
+        assert!(!Visibility::Followers.is_accepted());
+        assert!(Visibility::Private.is_accepted());
+    }
+}